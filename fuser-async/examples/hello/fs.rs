@@ -1,18 +1,12 @@
+//! A read-only filesystem with a single `hello.txt` greeting its reader.
+
 use async_trait::async_trait;
-use fuser::{FileAttr, FileType};
+use fuser::{consts::FOPEN_DIRECT_IO, FileAttr, FileType};
 use fuser_async::{
-    async_filesystem::AsyncFilesystem, errors::AsyncFilesystemError, mount::spawn_mount,
+    async_filesystem::{AsyncFilesystem, RequestContext},
+    errors::AsyncFilesystemError,
 };
-use log::info;
-use pretty_env_logger::env_logger::{Builder, Env};
 use std::time::{Duration, UNIX_EPOCH};
-use tokio::{
-    select,
-    signal::{
-        self,
-        unix::{signal, SignalKind},
-    },
-};
 
 const TTL: Duration = Duration::from_secs(1);
 
@@ -34,11 +28,9 @@ const HELLO_DIR_ATTR: FileAttr = FileAttr {
     blksize: 512,
 };
 
-const HELLO_TXT_CONTENT: &str = "Hello World!\n";
-
 const HELLO_TXT_ATTR: FileAttr = FileAttr {
     ino: 2,
-    size: 13,
+    size: 0,
     blocks: 1,
     atime: UNIX_EPOCH, // 1970-01-01 00:00:00
     mtime: UNIX_EPOCH,
@@ -54,15 +46,40 @@ const HELLO_TXT_ATTR: FileAttr = FileAttr {
     blksize: 512,
 };
 
-struct SimpleFS {}
+/// Greets the calling user, so each uid reads its own content.
+///
+/// The kernel caches attributes per inode rather than per caller, so `hello.txt`
+/// is given a zero TTL and opened with direct I/O: every caller then gets the size
+/// of its own content, and reads aren't served from another caller's pages.
+fn hello_txt_content(req: &RequestContext) -> String {
+    format!("Hello uid {}!\n", req.uid)
+}
+
+fn hello_txt_attr(req: &RequestContext) -> FileAttr {
+    let size = hello_txt_content(req).len() as u64;
+
+    FileAttr {
+        size,
+        blocks: size.div_ceil(HELLO_TXT_ATTR.blksize as u64),
+        ..HELLO_TXT_ATTR
+    }
+}
+
+pub struct SimpleFS {}
 
 #[async_trait]
 impl AsyncFilesystem for SimpleFS {
     type Error = AsyncFilesystemError;
-    async fn getattr(&self, ino: u64) -> Result<(Duration, FileAttr), AsyncFilesystemError> {
+    type Handle = ();
+
+    async fn getattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), AsyncFilesystemError> {
         match ino {
             1 => Ok((TTL, HELLO_DIR_ATTR)),
-            2 => Ok((TTL, HELLO_TXT_ATTR)),
+            2 => Ok((Duration::ZERO, hello_txt_attr(req))),
             _ => Err(AsyncFilesystemError::GetAttrError(
                 ino,
                 "No such file or directory".to_string(),
@@ -72,11 +89,12 @@ impl AsyncFilesystem for SimpleFS {
 
     async fn lookup(
         &self,
+        req: &RequestContext,
         parent: u64,
         name: &str,
     ) -> Result<(Duration, FileAttr, u64), AsyncFilesystemError> {
         match (parent, name) {
-            (1, "hello.txt") => Ok((TTL, HELLO_TXT_ATTR, 2)),
+            (1, "hello.txt") => Ok((Duration::ZERO, hello_txt_attr(req), 2)),
             _ => Err(AsyncFilesystemError::GetAttrError(
                 parent,
                 "No such file or directory".to_string(),
@@ -86,8 +104,9 @@ impl AsyncFilesystem for SimpleFS {
 
     async fn readdir(
         &self,
+        _req: &RequestContext,
         ino: u64,
-        _fh: u64,
+        _fh: &(),
         offset: i64,
     ) -> Result<Vec<(u64, i64, FileType, String)>, AsyncFilesystemError> {
         match ino {
@@ -104,24 +123,38 @@ impl AsyncFilesystem for SimpleFS {
                 }
                 Ok(entries)
             }
-            _ => Err(AsyncFilesystemError::ReadError(
-                ino,
-                "Not a directory".to_string(),
-            )),
+            _ => Err(AsyncFilesystemError::NotADirectory(ino)),
+        }
+    }
+
+    async fn open(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        _flags: i32,
+    ) -> Result<((), u32), AsyncFilesystemError> {
+        match ino {
+            2 => Ok(((), FOPEN_DIRECT_IO)),
+            _ => Ok(((), 0)),
         }
     }
 
     async fn read(
         &self,
+        req: &RequestContext,
         ino: u64,
-        _fh: u64,
+        _fh: &(),
         offset: i64,
-        _size: u32,
+        size: u32,
         _flags: i32,
         _lock: Option<u64>,
     ) -> Result<Vec<u8>, AsyncFilesystemError> {
         if ino == 2 {
-            Ok(HELLO_TXT_CONTENT.as_bytes()[offset as usize..].to_vec())
+            let content = hello_txt_content(req);
+            let start = (offset.max(0) as usize).min(content.len());
+            let end = start.saturating_add(size as usize).min(content.len());
+
+            Ok(content.as_bytes()[start..end].to_vec())
         } else {
             Err(AsyncFilesystemError::GetAttrError(
                 ino,
@@ -130,26 +163,3 @@ impl AsyncFilesystem for SimpleFS {
         }
     }
 }
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    Builder::from_env(Env::new().default_filter_or("info")).init();
-    let mountpoint = tempfile::tempdir().unwrap();
-
-    let umount = spawn_mount(SimpleFS {}, mountpoint, &[]).expect("Failed to mount filesystem");
-
-    let mut sig_term = signal(SignalKind::terminate())?;
-
-    select! {
-        _ = signal::ctrl_c() => {
-            info!("Received Ctrl-C, sending unmount signals");
-        }
-        _ = sig_term.recv() => {
-            info!("Received SIGTERM, sending unmount signal");
-        }
-    };
-
-    umount.await;
-
-    Ok(())
-}
@@ -0,0 +1,39 @@
+mod fs;
+
+use fs::SimpleFS;
+use fuser_async::mount::spawn_mount;
+use log::info;
+use pretty_env_logger::env_logger::{Builder, Env};
+use tokio::{
+    select,
+    signal::{
+        self,
+        unix::{signal, SignalKind},
+    },
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    Builder::from_env(Env::new().default_filter_or("info")).init();
+    let mountpoint = tempfile::tempdir().unwrap();
+
+    let mount =
+        spawn_mount(SimpleFS {}, mountpoint.path(), &[]).expect("Failed to mount filesystem");
+
+    info!("Mounted filesystem at {}", mount.mountpoint().display());
+
+    let mut sig_term = signal(SignalKind::terminate())?;
+
+    select! {
+        _ = signal::ctrl_c() => {
+            info!("Received Ctrl-C, sending unmount signals");
+        }
+        _ = sig_term.recv() => {
+            info!("Received SIGTERM, sending unmount signal");
+        }
+    };
+
+    mount.unmount().await?;
+
+    Ok(())
+}
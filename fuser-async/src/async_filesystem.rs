@@ -1,44 +1,470 @@
-use std::{fmt, time::Duration};
+use std::{
+    ffi::OsStr,
+    fmt,
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use async_trait::async_trait;
-use fuser::{FileAttr, FileType, Filesystem};
-use log::{debug, error};
+use fuser::{FileAttr, FileType, Filesystem, KernelConfig, TimeOrNow};
+use log::error;
 use tokio::runtime::Handle;
 
+use crate::{
+    errors::{AsyncFilesystemError, ToErrno},
+    handles::HandleTable,
+    mount::InFlight,
+};
+
+/// Identity of the process that issued a kernel request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestContext {
+    pub unique: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: u32,
+}
+
+impl From<&fuser::Request<'_>> for RequestContext {
+    fn from(req: &fuser::Request<'_>) -> Self {
+        Self {
+            unique: req.unique(),
+            uid: req.uid(),
+            gid: req.gid(),
+            pid: req.pid(),
+        }
+    }
+}
+
+impl RequestContext {
+    /// Check `mask` (a combination of `R_OK`, `W_OK` and `X_OK`, or `F_OK`) against
+    /// the permission bits of `attr` for the caller.
+    ///
+    /// Root may read and write anything, and execute anything with at least one
+    /// execute bit set.
+    pub fn can_access(&self, attr: &FileAttr, mask: i32) -> bool {
+        let mask = (mask & (libc::R_OK | libc::W_OK | libc::X_OK)) as u16;
+
+        if self.uid == 0 {
+            return mask & libc::X_OK as u16 == 0
+                || attr.kind == FileType::Directory
+                || attr.perm & 0o111 != 0;
+        }
+
+        // The supplementary groups are only looked up when the group and other
+        // bits give different answers.
+        let bits = if self.uid == attr.uid {
+            attr.perm >> 6
+        } else if self.gid == attr.gid
+            || ((attr.perm >> 3) ^ attr.perm) & mask != 0 && self.groups().contains(&attr.gid)
+        {
+            attr.perm >> 3
+        } else {
+            attr.perm
+        };
+
+        bits & mask == mask
+    }
+
+    /// Supplementary groups of the calling process, which the kernel doesn't send
+    /// with requests. They are read from `/proc` like libfuse's
+    /// `fuse_req_getgroups` does, and are empty once the process has exited.
+    pub fn groups(&self) -> Vec<u32> {
+        let path = format!("/proc/{}/task/{}/status", self.pid, self.pid);
+        let Ok(status) = std::fs::read_to_string(path) else {
+            return vec![];
+        };
+
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("Groups:"))
+            .map(|groups| {
+                groups
+                    .split_whitespace()
+                    .filter_map(|g| g.parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Filesystem statistics, in the units of `statvfs(3)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statfs {
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub bsize: u32,
+    pub namelen: u32,
+    pub frsize: u32,
+}
+
+impl Statfs {
+    /// Free blocks reported by default, since filesystems without a notion of
+    /// capacity would otherwise show as full in `df`.
+    pub const FREE_BLOCKS: u64 = 1 << 32;
+}
+
+impl Default for Statfs {
+    /// An empty filesystem of [`Statfs::FREE_BLOCKS`] blocks.
+    fn default() -> Self {
+        Self {
+            blocks: Self::FREE_BLOCKS,
+            bfree: Self::FREE_BLOCKS,
+            bavail: Self::FREE_BLOCKS,
+            files: 0,
+            ffree: 0,
+            bsize: 512,
+            namelen: 255,
+            frsize: 0,
+        }
+    }
+}
+
+/// Attributes to change in `setattr`, `None` leaving an attribute as it is.
+///
+/// Times set to "now" by the caller are resolved when the request is received.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SetAttr {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: Option<SystemTime>,
+    pub mtime: Option<SystemTime>,
+    pub ctime: Option<SystemTime>,
+    pub crtime: Option<SystemTime>,
+    pub flags: Option<u32>,
+}
+
+/// An asynchronous counterpart of [`fuser::Filesystem`].
+///
+/// Each kernel request is spawned as its own task on the tokio runtime, so the
+/// filesystem is shared between tasks through an [`Arc`] and its methods may run
+/// concurrently.
 #[async_trait]
-pub trait AsyncFilesystem {
-    type Error: fmt::Debug;
-    async fn getattr(&self, ino: u64) -> Result<(Duration, FileAttr), Self::Error>;
+pub trait AsyncFilesystem: Send + Sync + 'static {
+    type Error: fmt::Debug + Send + ToErrno + From<AsyncFilesystemError>;
+
+    /// State attached to an open file or directory.
+    ///
+    /// Handles returned by `open`, `opendir` and `create` are kept by the adapter
+    /// and passed back to every operation on the same `fh` until it is released.
+    /// Filesystems without per-open state can use `()`.
+    type Handle: Default + Send + Sync + 'static;
+
+    async fn getattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error>;
+
+    /// Change the attributes of a file, returning the new ones.
+    ///
+    /// `fh` is the handle the change was made through, e.g. for `ftruncate`.
+    /// Truncating a file opened with `O_TRUNC` also goes through here.
+    async fn setattr(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: Option<&Self::Handle>,
+        _attr: SetAttr,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
 
     async fn lookup(
         &self,
+        req: &RequestContext,
         parent: u64,
         name: &str,
     ) -> Result<(Duration, FileAttr, u64), Self::Error>;
 
     async fn readdir(
         &self,
+        req: &RequestContext,
         ino: u64,
-        fh: u64,
+        fh: &Self::Handle,
         offset: i64,
     ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error>;
 
+    /// List a directory together with the attributes of its entries, as
+    /// `(offset, name, ttl, attr, generation)`.
+    ///
+    /// The kernel uses this instead of `readdir` followed by a `lookup` per entry.
+    /// The default calls `readdir` and then `getattr` on every entry; filesystems
+    /// that can fetch both at once should override it.
+    async fn readdirplus(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+    ) -> Result<Vec<(i64, String, Duration, FileAttr, u64)>, Self::Error> {
+        let entries = self.readdir(req, ino, fh, offset).await?;
+        let mut r = Vec::with_capacity(entries.len());
+
+        for (ino, offset, _, name) in entries {
+            let (ttl, attr) = self.getattr(req, ino).await?;
+            r.push((offset, name, ttl, attr, 0));
+        }
+
+        Ok(r)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn read(
         &self,
+        req: &RequestContext,
         ino: u64,
-        fh: u64,
+        fh: &Self::Handle,
         offset: i64,
         size: u32,
         flags: i32,
         lock: Option<u64>,
     ) -> Result<Vec<u8>, Self::Error>;
+
+    /// Open a file, returning its handle and the open flags for the kernel.
+    async fn open(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _flags: i32,
+    ) -> Result<(Self::Handle, u32), Self::Error> {
+        Ok((Self::Handle::default(), 0))
+    }
+
+    /// Flush an open file, called on every `close` of a file descriptor.
+    async fn flush(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _lock_owner: u64,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Release an open file. Called exactly once for every `open` or `create`.
+    async fn release(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _flags: i32,
+        _lock: Option<u64>,
+        _flush: bool,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Open a directory, returning its handle and the open flags for the kernel.
+    async fn opendir(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _flags: i32,
+    ) -> Result<(Self::Handle, u32), Self::Error> {
+        Ok((Self::Handle::default(), 0))
+    }
+
+    /// Release an open directory. Called exactly once for every `opendir`.
+    async fn releasedir(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _flags: i32,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Write data to an open file, returning the number of bytes written.
+    #[allow(clippy::too_many_arguments)]
+    async fn write(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _offset: i64,
+        _data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+    ) -> Result<u32, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Create and open a file, returning its entry together with its handle and
+    /// the open flags.
+    ///
+    /// If this is not implemented the kernel falls back to `mknod` followed by `open`.
+    async fn create(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+    ) -> Result<(Duration, FileAttr, u64, Self::Handle, u32), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Create a regular file, character device, block device, fifo or socket node.
+    async fn mknod(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+        _mode: u32,
+        _umask: u32,
+        _rdev: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    async fn mkdir(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+        _mode: u32,
+        _umask: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    async fn unlink(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+    ) -> Result<(), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    async fn rmdir(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+    ) -> Result<(), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    async fn rename(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+        _newparent: u64,
+        _newname: &str,
+        _flags: u32,
+    ) -> Result<(), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Read the target of a symbolic link.
+    async fn readlink(&self, _req: &RequestContext, _ino: u64) -> Result<Vec<u8>, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Create a symbolic link `name` in `parent` pointing to `link`.
+    async fn symlink(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+        _link: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Create a hard link `newname` in `newparent` to the existing inode `ino`.
+    async fn link(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _newparent: u64,
+        _newname: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    async fn statfs(&self, _req: &RequestContext, _ino: u64) -> Result<Statfs, Self::Error> {
+        Ok(Statfs::default())
+    }
+
+    /// Check the caller's permissions on an inode, see [`RequestContext::can_access`].
+    ///
+    /// If this is not implemented the kernel stops asking and grants every access.
+    async fn access(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _mask: i32,
+    ) -> Result<(), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Get the value of an extended attribute.
+    ///
+    /// The adapter takes care of the size probe protocol: the full value is always
+    /// returned here and truncated or replaced by its length as the kernel requests.
+    /// Missing attributes should be reported with [`AsyncFilesystemError::NoSuchAttribute`].
+    async fn getxattr(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _name: &str,
+    ) -> Result<Vec<u8>, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// List the names of the extended attributes of an inode.
+    async fn listxattr(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+    ) -> Result<Vec<String>, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    /// Set an extended attribute. `flags` carries `XATTR_CREATE` or `XATTR_REPLACE`.
+    async fn setxattr(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _name: &str,
+        _value: &[u8],
+        _flags: i32,
+        _position: u32,
+    ) -> Result<(), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
+
+    async fn removexattr(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _name: &str,
+    ) -> Result<(), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented.into())
+    }
 }
 
 pub(crate) struct AsyncFsImpl<FS>
 where
     FS: AsyncFilesystem,
 {
-    fs: FS,
+    fs: Arc<FS>,
+    handles: Arc<HandleTable<FS::Handle>>,
+    in_flight: Arc<InFlight>,
     rt: Handle,
 }
 
@@ -46,81 +472,212 @@ impl<FS> AsyncFsImpl<FS>
 where
     FS: AsyncFilesystem,
 {
-    pub fn new(fs: FS, rt: Handle) -> Self {
-        Self { fs, rt }
+    pub fn new(fs: Arc<FS>, rt: Handle, in_flight: Arc<InFlight>) -> Self {
+        Self {
+            fs,
+            handles: Arc::new(HandleTable::new()),
+            in_flight,
+            rt,
+        }
     }
+
+    /// Run a request on the runtime, leaving the session thread free to read the next one.
+    fn spawn<F>(&self, f: impl FnOnce(Arc<FS>) -> F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let Some(guard) = self.in_flight.enter() else {
+            // The filesystem is being unmounted: dropping the request's reply
+            // answers it with EIO.
+            return;
+        };
+        let fut = f(self.fs.clone());
+
+        self.rt.spawn(async move {
+            fut.await;
+            drop(guard);
+        });
+    }
+}
+
+fn to_name(name: &OsStr) -> Option<String> {
+    name.to_str().map(str::to_owned)
 }
 
 impl<FS> Filesystem for AsyncFsImpl<FS>
 where
     FS: AsyncFilesystem,
 {
-    fn getattr(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyAttr) {
-        match self.rt.block_on(self.fs.getattr(ino)) {
-            Ok((ttl, attr)) => {
-                debug!("getattr({}) = {:?}", ino, attr);
-                reply.attr(&ttl, &attr)
+    fn init(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        config: &mut KernelConfig,
+    ) -> Result<(), libc::c_int> {
+        // Have the kernel list directories with `readdirplus` when it supports it.
+        let _ = config.add_capabilities(fuser::consts::FUSE_DO_READDIRPLUS);
+
+        Ok(())
+    }
+
+    fn getattr(&mut self, req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyAttr) {
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.getattr(&req, ino).await {
+                Ok((ttl, attr)) => reply.attr(&ttl, &attr),
+                Err(e) => {
+                    error!("getattr({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
             }
-            Err(e) => {
-                error!("getattr({}) failed: {:?}", ino, e);
-                reply.error(libc::ENOENT);
+        });
+    }
+
+    fn setattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        fh: Option<u64>,
+        crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+        reply: fuser::ReplyAttr,
+    ) {
+        let handle = match fh.map(|fh| self.handles.get(fh)) {
+            Some(None) => return reply.error(libc::EBADF),
+            Some(handle) => handle,
+            None => None,
+        };
+
+        let to_time = |t: TimeOrNow| match t {
+            TimeOrNow::SpecificTime(t) => t,
+            TimeOrNow::Now => SystemTime::now(),
+        };
+        let attr = SetAttr {
+            mode,
+            uid,
+            gid,
+            size,
+            atime: atime.map(to_time),
+            mtime: mtime.map(to_time),
+            ctime,
+            crtime,
+            flags,
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.setattr(&req, ino, handle.as_deref(), attr).await {
+                Ok((ttl, attr)) => reply.attr(&ttl, &attr),
+                Err(e) => {
+                    error!("setattr({}, {:?}) failed: {:?}", ino, attr, e);
+                    reply.error(e.errno());
+                }
             }
-        }
+        });
     }
 
     fn lookup(
         &mut self,
-        _req: &fuser::Request<'_>,
+        req: &fuser::Request<'_>,
         parent: u64,
-        name: &std::ffi::OsStr,
+        name: &OsStr,
         reply: fuser::ReplyEntry,
     ) {
-        let r = name
-            .to_str()
-            .map(|n| self.rt.block_on(self.fs.lookup(parent, n)));
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
 
-        debug!("lookup({:?}) = {:?}", name, r);
+        let req = RequestContext::from(req);
 
-        match r {
-            Some(Ok((ttl, attr, generation))) => reply.entry(&ttl, &attr, generation),
+        self.spawn(|fs| async move {
+            let r = fs.lookup(&req, parent, &name).await;
 
-            Some(Err(e)) => {
-                error!("lookup({:?}) failed: {:?}", name, e);
-                reply.error(libc::ENOENT);
+            match r {
+                Ok((ttl, attr, generation)) => reply.entry(&ttl, &attr, generation),
+                Err(e) => {
+                    error!("lookup({:?}) failed: {:?}", name, e);
+                    reply.error(e.errno());
+                }
             }
-            None => reply.error(libc::ENOENT),
-        }
+        });
     }
 
     fn readdir(
         &mut self,
-        _req: &fuser::Request<'_>,
+        req: &fuser::Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
         mut reply: fuser::ReplyDirectory,
     ) {
-        match self.rt.block_on(self.fs.readdir(ino, fh, offset)) {
-            Ok(entries) => {
-                debug!("readdir({}) = {:?}", ino, entries);
-                for (ino, o, kind, name) in entries {
-                    if reply.add(ino, o, kind, name) {
-                        break;
+        let Some(handle) = self.handles.get(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.readdir(&req, ino, &handle, offset).await {
+                Ok(entries) => {
+                    for (ino, o, kind, name) in entries {
+                        if reply.add(ino, o, kind, name) {
+                            break;
+                        }
                     }
-                }
 
-                reply.ok();
+                    reply.ok();
+                }
+                Err(e) => {
+                    error!("readdir({}) failed: {:?}", ino, e);
+                    reply.error(e.errno())
+                }
             }
-            Err(e) => {
-                error!("readdir({}) failed: {:?}", ino, e);
-                reply.error(libc::ENOENT)
+        });
+    }
+
+    fn readdirplus(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: fuser::ReplyDirectoryPlus,
+    ) {
+        let Some(handle) = self.handles.get(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.readdirplus(&req, ino, &handle, offset).await {
+                Ok(entries) => {
+                    for (o, name, ttl, attr, generation) in entries {
+                        if reply.add(attr.ino, o, name, &ttl, &attr, generation) {
+                            break;
+                        }
+                    }
+
+                    reply.ok();
+                }
+                Err(e) => {
+                    error!("readdirplus({}) failed: {:?}", ino, e);
+                    reply.error(e.errno())
+                }
             }
-        }
+        });
     }
 
     fn read(
         &mut self,
-        _req: &fuser::Request<'_>,
+        req: &fuser::Request<'_>,
         ino: u64,
         fh: u64,
         offset: i64,
@@ -129,15 +686,587 @@ where
         lock_owner: Option<u64>,
         reply: fuser::ReplyData,
     ) {
-        match self
-            .rt
-            .block_on(self.fs.read(ino, fh, offset, size, flags, lock_owner))
-        {
-            Ok(data) => reply.data(&data),
-            Err(e) => {
-                error!("read({}) failed: {:?}", ino, e);
-                reply.error(libc::ENOENT);
+        let Some(handle) = self.handles.get(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs
+                .read(&req, ino, &handle, offset, size, flags, lock_owner)
+                .await
+            {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    error!("read({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn open(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        let req = RequestContext::from(req);
+        let handles = self.handles.clone();
+
+        self.spawn(|fs| async move {
+            match fs.open(&req, ino, flags).await {
+                Ok((handle, flags)) => reply.opened(handles.insert(handle), flags),
+                Err(e) => {
+                    error!("open({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn flush(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(handle) = self.handles.get(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.flush(&req, ino, &handle, lock_owner).await {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("flush({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn release(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        lock_owner: Option<u64>,
+        flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(handle) = self.handles.remove(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs
+                .release(&req, ino, &handle, flags, lock_owner, flush)
+                .await
+            {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("release({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
             }
+        });
+    }
+
+    fn opendir(&mut self, req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        let req = RequestContext::from(req);
+        let handles = self.handles.clone();
+
+        self.spawn(|fs| async move {
+            match fs.opendir(&req, ino, flags).await {
+                Ok((handle, flags)) => reply.opened(handles.insert(handle), flags),
+                Err(e) => {
+                    error!("opendir({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn releasedir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        flags: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(handle) = self.handles.remove(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.releasedir(&req, ino, &handle, flags).await {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("releasedir({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn write(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock_owner: Option<u64>,
+        reply: fuser::ReplyWrite,
+    ) {
+        let Some(handle) = self.handles.get(fh) else {
+            return reply.error(libc::EBADF);
+        };
+        let req = RequestContext::from(req);
+        let data = data.to_vec();
+
+        self.spawn(|fs| async move {
+            match fs
+                .write(
+                    &req,
+                    ino,
+                    &handle,
+                    offset,
+                    &data,
+                    write_flags,
+                    flags,
+                    lock_owner,
+                )
+                .await
+            {
+                Ok(written) => reply.written(written),
+                Err(e) => {
+                    error!("write({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn create(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+        reply: fuser::ReplyCreate,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+        let handles = self.handles.clone();
+
+        self.spawn(|fs| async move {
+            let r = fs.create(&req, parent, &name, mode, umask, flags).await;
+
+            match r {
+                Ok((ttl, attr, generation, handle, flags)) => {
+                    reply.created(&ttl, &attr, generation, handles.insert(handle), flags)
+                }
+                Err(e) => {
+                    error!("create({}, {:?}) failed: {:?}", parent, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn mknod(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs.mknod(&req, parent, &name, mode, umask, rdev).await;
+
+            match r {
+                Ok((ttl, attr, generation)) => reply.entry(&ttl, &attr, generation),
+                Err(e) => {
+                    error!("mknod({}, {:?}) failed: {:?}", parent, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: fuser::ReplyEntry,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs.mkdir(&req, parent, &name, mode, umask).await;
+
+            match r {
+                Ok((ttl, attr, generation)) => reply.entry(&ttl, &attr, generation),
+                Err(e) => {
+                    error!("mkdir({}, {:?}) failed: {:?}", parent, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn unlink(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs.unlink(&req, parent, &name).await;
+
+            match r {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("unlink({}, {:?}) failed: {:?}", parent, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn rmdir(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs.rmdir(&req, parent, &name).await;
+
+            match r {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("rmdir({}, {:?}) failed: {:?}", parent, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn rename(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let (Some(name), Some(newname)) = (to_name(name), to_name(newname)) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs
+                .rename(&req, parent, &name, newparent, &newname, flags)
+                .await;
+
+            match r {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!(
+                        "rename({}, {:?}, {}, {:?}) failed: {:?}",
+                        parent, name, newparent, newname, e
+                    );
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn readlink(&mut self, req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.readlink(&req, ino).await {
+                Ok(target) => reply.data(&target),
+                Err(e) => {
+                    error!("readlink({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn symlink(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        link: &Path,
+        reply: fuser::ReplyEntry,
+    ) {
+        let (Some(name), Some(link)) = (to_name(name), to_name(link.as_os_str())) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs.symlink(&req, parent, &name, &link).await;
+
+            match r {
+                Ok((ttl, attr, generation)) => reply.entry(&ttl, &attr, generation),
+                Err(e) => {
+                    error!(
+                        "symlink({}, {:?}, {:?}) failed: {:?}",
+                        parent, name, link, e
+                    );
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn link(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: fuser::ReplyEntry,
+    ) {
+        let Some(newname) = to_name(newname) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            let r = fs.link(&req, ino, newparent, &newname).await;
+
+            match r {
+                Ok((ttl, attr, generation)) => reply.entry(&ttl, &attr, generation),
+                Err(e) => {
+                    error!(
+                        "link({}, {}, {:?}) failed: {:?}",
+                        ino, newparent, newname, e
+                    );
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn statfs(&mut self, req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyStatfs) {
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.statfs(&req, ino).await {
+                Ok(st) => reply.statfs(
+                    st.blocks, st.bfree, st.bavail, st.files, st.ffree, st.bsize, st.namelen,
+                    st.frsize,
+                ),
+                Err(e) => {
+                    error!("statfs({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn access(&mut self, req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.access(&req, ino, mask).await {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("access({}, {:o}) failed: {:?}", ino, mask, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn getxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.getxattr(&req, ino, &name).await {
+                Ok(value) => reply_xattr(reply, XattrReply::new(size, value)),
+                Err(e) => {
+                    error!("getxattr({}, {:?}) failed: {:?}", ino, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn listxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.listxattr(&req, ino).await {
+                Ok(names) => reply_xattr(reply, XattrReply::new(size, xattr_list(names))),
+                Err(e) => {
+                    error!("listxattr({}) failed: {:?}", ino, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn setxattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+        let value = value.to_vec();
+
+        self.spawn(|fs| async move {
+            match fs.setxattr(&req, ino, &name, &value, flags, position).await {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("setxattr({}, {:?}) failed: {:?}", ino, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+
+    fn removexattr(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let Some(name) = to_name(name) else {
+            return reply.error(libc::EINVAL);
+        };
+
+        let req = RequestContext::from(req);
+
+        self.spawn(|fs| async move {
+            match fs.removexattr(&req, ino, &name).await {
+                Ok(()) => reply.ok(),
+                Err(e) => {
+                    error!("removexattr({}, {:?}) failed: {:?}", ino, name, e);
+                    reply.error(e.errno());
+                }
+            }
+        });
+    }
+}
+
+/// The answer to a `getxattr` or `listxattr` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XattrReply {
+    /// The length of the value, for a request with a `size` of 0.
+    Size(u32),
+    Data(Vec<u8>),
+}
+
+impl XattrReply {
+    /// Answer a request for `size` bytes of `data`: a `size` of 0 asks for the
+    /// length only, and a buffer too small for the value is an `ERANGE`.
+    pub fn new(size: u32, data: Vec<u8>) -> Result<Self, libc::c_int> {
+        if size == 0 {
+            Ok(XattrReply::Size(data.len() as u32))
+        } else if data.len() > size as usize {
+            Err(libc::ERANGE)
+        } else {
+            Ok(XattrReply::Data(data))
         }
     }
 }
+
+/// The value of a `listxattr` request: the names, each followed by a NUL byte.
+pub(crate) fn xattr_list(names: Vec<String>) -> Vec<u8> {
+    let mut list = Vec::new();
+    for name in names {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+    }
+    list
+}
+
+fn reply_xattr(reply: fuser::ReplyXattr, r: Result<XattrReply, libc::c_int>) {
+    match r {
+        Ok(XattrReply::Size(size)) => reply.size(size),
+        Ok(XattrReply::Data(data)) => reply.data(&data),
+        Err(errno) => reply.error(errno),
+    }
+}
@@ -0,0 +1,651 @@
+use std::{
+    collections::{hash_map, BTreeMap, HashMap, HashSet},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+
+use crate::async_filesystem::{AsyncFilesystem, RequestContext, SetAttr, Statfs};
+
+const DEFAULT_CAPACITY: usize = 10_000;
+const DEFAULT_READDIR_TTL: Duration = Duration::from_secs(1);
+
+type DirEntries = Vec<(u64, i64, FileType, String)>;
+
+/// Hit and miss counters of a [`CachingFilesystem`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub getattr_hits: u64,
+    pub getattr_misses: u64,
+    pub lookup_hits: u64,
+    pub lookup_misses: u64,
+    pub readdir_hits: u64,
+    pub readdir_misses: u64,
+    pub evictions: u64,
+}
+
+struct Entry<V> {
+    /// Position of the entry in `Entries::expiry`.
+    expiry: (Instant, u64),
+    value: V,
+}
+
+struct Entries<K, V> {
+    values: HashMap<K, Entry<V>>,
+    /// Keys by expiry, made unique by an insertion counter, so that the entries
+    /// closest to expiry are evicted first without scanning.
+    expiry: BTreeMap<(Instant, u64), K>,
+    /// Keys by the inode their entry describes, so that invalidating an inode
+    /// doesn't scan the whole cache.
+    by_ino: HashMap<u64, HashSet<K>>,
+    ino: fn(&K, &V) -> u64,
+    next: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> Entries<K, V> {
+    fn remove(&mut self, key: &K) -> Option<Entry<V>> {
+        let entry = self.values.remove(key)?;
+        self.expiry.remove(&entry.expiry);
+
+        if let hash_map::Entry::Occupied(mut keys) =
+            self.by_ino.entry((self.ino)(key, &entry.value))
+        {
+            keys.get_mut().remove(key);
+            if keys.get().is_empty() {
+                keys.remove();
+            }
+        }
+
+        Some(entry)
+    }
+}
+
+struct TtlCache<K, V> {
+    entries: Mutex<Entries<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> TtlCache<K, V> {
+    /// A cache whose entries describe the inode returned by `ino`.
+    fn new(ino: fn(&K, &V) -> u64) -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                values: HashMap::new(),
+                expiry: BTreeMap::new(),
+                by_ino: HashMap::new(),
+                ino,
+                next: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached value and its remaining time to live.
+    fn get(&self, key: &K) -> Option<(Duration, V)> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        let r = match entries.values.get(key) {
+            Some(entry) if entry.expiry.0 > now => {
+                Some((entry.expiry.0 - now, entry.value.clone()))
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        };
+
+        match r {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        r
+    }
+
+    fn insert(&self, key: K, ttl: Duration, value: V, capacity: usize) {
+        if ttl.is_zero() || capacity == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+
+        entries.remove(&key);
+
+        // Expired entries come first, then the ones closest to expiry.
+        let mut evicted = 0;
+        while let Some(first) = entries.expiry.first_entry() {
+            if entries.values.len() < capacity && first.key().0 > now {
+                break;
+            }
+
+            let key = first.get().clone();
+            entries.remove(&key);
+            evicted += 1;
+        }
+        self.evictions.fetch_add(evicted, Ordering::Relaxed);
+
+        let position = (now + ttl, entries.next);
+        entries.next += 1;
+        entries.expiry.insert(position, key.clone());
+        let ino = (entries.ino)(&key, &value);
+        entries.by_ino.entry(ino).or_default().insert(key.clone());
+        entries.values.insert(
+            key,
+            Entry {
+                expiry: position,
+                value,
+            },
+        );
+    }
+
+    fn remove(&self, key: &K) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(key).map(|entry| entry.value)
+    }
+
+    /// Remove the entries describing `ino`.
+    fn remove_ino(&self, ino: u64) {
+        let mut entries = self.entries.lock().unwrap();
+
+        for key in entries.by_ino.remove(&ino).unwrap_or_default() {
+            entries.remove(&key);
+        }
+    }
+
+    fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.values.clear();
+        entries.expiry.clear();
+        entries.by_ino.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().values.len()
+    }
+}
+
+/// Memoises `getattr`, `lookup` and `readdir` of the wrapped filesystem.
+///
+/// Attributes and entries are kept for the TTL returned by the inner filesystem,
+/// directory listings for a fixed TTL. Operations that modify the filesystem
+/// through this wrapper invalidate the affected entries; changes made behind its
+/// back must be reported with the `invalidate*` methods.
+///
+/// Cached results are shared between callers, so filesystems whose answers
+/// depend on the [`RequestContext`] should not be wrapped.
+pub struct CachingFilesystem<FS: AsyncFilesystem> {
+    inner: FS,
+    capacity: usize,
+    readdir_ttl: Duration,
+    attrs: TtlCache<u64, FileAttr>,
+    entries: TtlCache<(u64, String), (FileAttr, u64)>,
+    dirs: TtlCache<(u64, i64), DirEntries>,
+}
+
+impl<FS: AsyncFilesystem> CachingFilesystem<FS> {
+    pub fn new(inner: FS) -> Self {
+        Self {
+            inner,
+            capacity: DEFAULT_CAPACITY,
+            readdir_ttl: DEFAULT_READDIR_TTL,
+            attrs: TtlCache::new(|ino, _| *ino),
+            entries: TtlCache::new(|_, (attr, _)| attr.ino),
+            dirs: TtlCache::new(|(ino, _), _| *ino),
+        }
+    }
+
+    /// Maximum number of entries kept in each of the attribute, entry and
+    /// directory caches.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// How long directory listings are kept. `Duration::ZERO` disables caching them.
+    pub fn with_readdir_ttl(mut self, ttl: Duration) -> Self {
+        self.readdir_ttl = ttl;
+        self
+    }
+
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            getattr_hits: self.attrs.hits.load(Ordering::Relaxed),
+            getattr_misses: self.attrs.misses.load(Ordering::Relaxed),
+            lookup_hits: self.entries.hits.load(Ordering::Relaxed),
+            lookup_misses: self.entries.misses.load(Ordering::Relaxed),
+            readdir_hits: self.dirs.hits.load(Ordering::Relaxed),
+            readdir_misses: self.dirs.misses.load(Ordering::Relaxed),
+            evictions: self.attrs.evictions.load(Ordering::Relaxed)
+                + self.entries.evictions.load(Ordering::Relaxed)
+                + self.dirs.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of cached attributes, entries and directory pages.
+    pub fn len(&self) -> usize {
+        self.attrs.len() + self.entries.len() + self.dirs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget the attributes of `ino` and every entry resolving to it.
+    pub fn invalidate(&self, ino: u64) {
+        self.attrs.remove_ino(ino);
+        self.entries.remove_ino(ino);
+    }
+
+    /// Forget the entry `name` in `parent`, and the attributes and listing of
+    /// `parent`.
+    pub fn invalidate_entry(&self, parent: u64, name: &str) {
+        self.entries.remove(&(parent, name.to_owned()));
+        self.attrs.remove(&parent);
+        self.invalidate_dir(parent);
+    }
+
+    /// Forget the listing of the directory `ino`.
+    pub fn invalidate_dir(&self, ino: u64) {
+        self.dirs.remove_ino(ino);
+    }
+
+    pub fn clear(&self) {
+        self.attrs.clear();
+        self.entries.clear();
+        self.dirs.clear();
+    }
+
+    /// Forget an entry that is about to be removed or replaced, along with the
+    /// inode it resolves to.
+    fn forget_entry(&self, parent: u64, name: &str) {
+        if let Some((attr, _)) = self.entries.remove(&(parent, name.to_owned())) {
+            self.invalidate(attr.ino);
+        }
+        self.invalidate_entry(parent, name);
+    }
+}
+
+#[async_trait]
+impl<FS: AsyncFilesystem> AsyncFilesystem for CachingFilesystem<FS> {
+    type Error = FS::Error;
+    type Handle = FS::Handle;
+
+    async fn getattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        if let Some(r) = self.attrs.get(&ino) {
+            return Ok(r);
+        }
+
+        let (ttl, attr) = self.inner.getattr(req, ino).await?;
+        self.attrs.insert(ino, ttl, attr, self.capacity);
+
+        Ok((ttl, attr))
+    }
+
+    async fn setattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: Option<&Self::Handle>,
+        attr: SetAttr,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        self.invalidate(ino);
+        let r = self.inner.setattr(req, ino, fh, attr).await;
+        // A getattr racing with the change may have cached the old attributes.
+        self.invalidate(ino);
+        r
+    }
+
+    async fn lookup(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let key = (parent, name.to_owned());
+
+        if let Some((ttl, (attr, generation))) = self.entries.get(&key) {
+            return Ok((ttl, attr, generation));
+        }
+
+        let (ttl, attr, generation) = self.inner.lookup(req, parent, name).await?;
+        self.entries
+            .insert(key, ttl, (attr, generation), self.capacity);
+        self.attrs.insert(attr.ino, ttl, attr, self.capacity);
+
+        Ok((ttl, attr, generation))
+    }
+
+    async fn readdir(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error> {
+        if let Some((_, entries)) = self.dirs.get(&(ino, offset)) {
+            return Ok(entries);
+        }
+
+        let entries = self.inner.readdir(req, ino, fh, offset).await?;
+        self.dirs.insert(
+            (ino, offset),
+            self.readdir_ttl,
+            entries.clone(),
+            self.capacity,
+        );
+
+        Ok(entries)
+    }
+
+    /// Not cached itself, but fills the attribute and entry caches so that the
+    /// `getattr` and `lookup` calls following a listing are hits.
+    async fn readdirplus(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+    ) -> Result<Vec<(i64, String, Duration, FileAttr, u64)>, Self::Error> {
+        let entries = self.inner.readdirplus(req, ino, fh, offset).await?;
+
+        for (_, name, ttl, attr, generation) in &entries {
+            self.attrs.insert(attr.ino, *ttl, *attr, self.capacity);
+
+            if name != "." && name != ".." {
+                self.entries.insert(
+                    (ino, name.clone()),
+                    *ttl,
+                    (*attr, *generation),
+                    self.capacity,
+                );
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn read(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<Vec<u8>, Self::Error> {
+        self.inner
+            .read(req, ino, fh, offset, size, flags, lock)
+            .await
+    }
+
+    async fn open(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        flags: i32,
+    ) -> Result<(Self::Handle, u32), Self::Error> {
+        self.inner.open(req, ino, flags).await
+    }
+
+    async fn flush(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        lock_owner: u64,
+    ) -> Result<(), Self::Error> {
+        let r = self.inner.flush(req, ino, fh, lock_owner).await;
+        self.invalidate(ino);
+        r
+    }
+
+    async fn release(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        flags: i32,
+        lock: Option<u64>,
+        flush: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.release(req, ino, fh, flags, lock, flush).await
+    }
+
+    async fn opendir(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        flags: i32,
+    ) -> Result<(Self::Handle, u32), Self::Error> {
+        self.inner.opendir(req, ino, flags).await
+    }
+
+    async fn releasedir(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        flags: i32,
+    ) -> Result<(), Self::Error> {
+        self.inner.releasedir(req, ino, fh, flags).await
+    }
+
+    async fn write(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<u32, Self::Error> {
+        self.invalidate(ino);
+        let r = self
+            .inner
+            .write(req, ino, fh, offset, data, write_flags, flags, lock)
+            .await;
+        self.invalidate(ino);
+        r
+    }
+
+    async fn create(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+    ) -> Result<(Duration, FileAttr, u64, Self::Handle, u32), Self::Error> {
+        self.invalidate_entry(parent, name);
+        let r = self
+            .inner
+            .create(req, parent, name, mode, umask, flags)
+            .await;
+        self.invalidate_entry(parent, name);
+        r
+    }
+
+    async fn mknod(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let r = self.inner.mknod(req, parent, name, mode, umask, rdev).await;
+        self.invalidate_entry(parent, name);
+        r
+    }
+
+    async fn mkdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let r = self.inner.mkdir(req, parent, name, mode, umask).await;
+        self.invalidate_entry(parent, name);
+        r
+    }
+
+    async fn unlink(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        self.forget_entry(parent, name);
+        let r = self.inner.unlink(req, parent, name).await;
+        // A lookup racing with the removal may have cached the entry again.
+        self.forget_entry(parent, name);
+        r
+    }
+
+    async fn rmdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        // The listing of the removed directory would otherwise outlive it, and be
+        // served for a new directory reusing its inode.
+        let ino = self.inner.lookup(req, parent, name).await.ok();
+
+        self.forget_entry(parent, name);
+        let r = self.inner.rmdir(req, parent, name).await;
+        self.forget_entry(parent, name);
+        if let Some((_, attr, _)) = ino {
+            self.invalidate_dir(attr.ino);
+        }
+        r
+    }
+
+    async fn rename(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+        flags: u32,
+    ) -> Result<(), Self::Error> {
+        self.forget_entry(parent, name);
+        self.forget_entry(newparent, newname);
+        let r = self
+            .inner
+            .rename(req, parent, name, newparent, newname, flags)
+            .await;
+        self.forget_entry(parent, name);
+        self.forget_entry(newparent, newname);
+        r
+    }
+
+    async fn readlink(&self, req: &RequestContext, ino: u64) -> Result<Vec<u8>, Self::Error> {
+        self.inner.readlink(req, ino).await
+    }
+
+    async fn symlink(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        link: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let r = self.inner.symlink(req, parent, name, link).await;
+        self.invalidate_entry(parent, name);
+        r
+    }
+
+    async fn link(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        newparent: u64,
+        newname: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let r = self.inner.link(req, ino, newparent, newname).await;
+        // The link count of the target changes as well.
+        self.invalidate(ino);
+        self.invalidate_entry(newparent, newname);
+        r
+    }
+
+    async fn statfs(&self, req: &RequestContext, ino: u64) -> Result<Statfs, Self::Error> {
+        self.inner.statfs(req, ino).await
+    }
+
+    async fn access(&self, req: &RequestContext, ino: u64, mask: i32) -> Result<(), Self::Error> {
+        self.inner.access(req, ino, mask).await
+    }
+
+    async fn getxattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> Result<Vec<u8>, Self::Error> {
+        self.inner.getxattr(req, ino, name).await
+    }
+
+    async fn listxattr(&self, req: &RequestContext, ino: u64) -> Result<Vec<String>, Self::Error> {
+        self.inner.listxattr(req, ino).await
+    }
+
+    async fn setxattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+    ) -> Result<(), Self::Error> {
+        let r = self
+            .inner
+            .setxattr(req, ino, name, value, flags, position)
+            .await;
+        self.invalidate(ino);
+        r
+    }
+
+    async fn removexattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        let r = self.inner.removexattr(req, ino, name).await;
+        self.invalidate(ino);
+        r
+    }
+}
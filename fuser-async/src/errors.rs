@@ -1,12 +1,21 @@
-use std::{ffi::OsString, io};
+use std::{ffi::OsString, io, time::Duration};
 
+use libc::c_int;
 use thiserror::Error;
 
+/// Maps a filesystem error to the errno reported to the kernel.
+pub trait ToErrno {
+    fn errno(&self) -> c_int;
+}
+
 #[derive(Error, Debug)]
 pub enum AsyncFilesystemError {
     #[error("Mount error: {0}")]
     MountError(#[from] io::Error),
 
+    #[error("Unmount error: {0}")]
+    UnmountError(String),
+
     #[error("Getattr error for ino {0}: {1}")]
     GetAttrError(u64, String),
 
@@ -15,4 +24,72 @@ pub enum AsyncFilesystemError {
 
     #[error("invalid utf8: {0:?}")]
     InvalidUtf8(OsString),
+
+    #[error("Not implemented")]
+    NotImplemented,
+
+    #[error("Permission denied for ino {0}")]
+    PermissionDenied(u64),
+
+    #[error("ino {0} is not a directory")]
+    NotADirectory(u64),
+
+    #[error("ino {0} is a directory")]
+    IsADirectory(u64),
+
+    #[error("{0} timed out after {1:?}")]
+    TimedOut(&'static str, Duration),
+
+    #[error("ino {0} has no attribute {1:?}")]
+    NoSuchAttribute(u64, String),
+
+    #[error("ino {0} is not a symlink")]
+    NotASymlink(u64),
+
+    #[error("{1:?} already exists in ino {0}")]
+    AlreadyExists(u64, String),
+
+    #[error("Directory ino {0} is not empty")]
+    NotEmpty(u64),
+
+    #[error("Read-only filesystem")]
+    ReadOnly,
+
+    #[error("ino {0} is not open for writing")]
+    NotWritable(u64),
+
+    #[error("ino {0} cannot be moved into its own subtree")]
+    MoveIntoSubtree(u64),
+
+    #[error("Operation not permitted on ino {0}")]
+    NotPermitted(u64),
+
+    #[error("ino {0} would exceed the maximum file size")]
+    FileTooLarge(u64),
+}
+
+impl ToErrno for AsyncFilesystemError {
+    fn errno(&self) -> c_int {
+        match self {
+            AsyncFilesystemError::MountError(e) => e.raw_os_error().unwrap_or(libc::EIO),
+            AsyncFilesystemError::UnmountError(_) => libc::EIO,
+            AsyncFilesystemError::GetAttrError(_, _) => libc::ENOENT,
+            AsyncFilesystemError::ReadError(_, _) => libc::EIO,
+            AsyncFilesystemError::InvalidUtf8(_) => libc::EINVAL,
+            AsyncFilesystemError::NotImplemented => libc::ENOSYS,
+            AsyncFilesystemError::PermissionDenied(_) => libc::EACCES,
+            AsyncFilesystemError::NotADirectory(_) => libc::ENOTDIR,
+            AsyncFilesystemError::IsADirectory(_) => libc::EISDIR,
+            AsyncFilesystemError::TimedOut(_, _) => libc::ETIMEDOUT,
+            AsyncFilesystemError::NoSuchAttribute(_, _) => libc::ENODATA,
+            AsyncFilesystemError::NotASymlink(_) => libc::EINVAL,
+            AsyncFilesystemError::AlreadyExists(_, _) => libc::EEXIST,
+            AsyncFilesystemError::NotEmpty(_) => libc::ENOTEMPTY,
+            AsyncFilesystemError::ReadOnly => libc::EROFS,
+            AsyncFilesystemError::NotWritable(_) => libc::EBADF,
+            AsyncFilesystemError::MoveIntoSubtree(_) => libc::EINVAL,
+            AsyncFilesystemError::NotPermitted(_) => libc::EPERM,
+            AsyncFilesystemError::FileTooLarge(_) => libc::EFBIG,
+        }
+    }
 }
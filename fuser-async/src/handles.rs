@@ -0,0 +1,37 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Maps the `fh` numbers handed to the kernel to the handles returned by
+/// `open`, `opendir` and `create`.
+pub(crate) struct HandleTable<H> {
+    next: AtomicU64,
+    handles: Mutex<HashMap<u64, Arc<H>>>,
+}
+
+impl<H> HandleTable<H> {
+    pub fn new() -> Self {
+        Self {
+            next: AtomicU64::new(1),
+            handles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn insert(&self, handle: H) -> u64 {
+        let fh = self.next.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(fh, Arc::new(handle));
+        fh
+    }
+
+    pub fn get(&self, fh: u64) -> Option<Arc<H>> {
+        self.handles.lock().unwrap().get(&fh).cloned()
+    }
+
+    pub fn remove(&self, fh: u64) -> Option<Arc<H>> {
+        self.handles.lock().unwrap().remove(&fh)
+    }
+}
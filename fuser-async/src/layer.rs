@@ -0,0 +1,994 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+use log::{debug, warn};
+use tokio::sync::Semaphore;
+
+use crate::{
+    async_filesystem::{AsyncFilesystem, RequestContext, SetAttr, Statfs},
+    cache::CachingFilesystem,
+    errors::AsyncFilesystemError,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Wraps an [`AsyncFilesystem`] into another one.
+pub trait Layer<FS: AsyncFilesystem> {
+    type Filesystem: AsyncFilesystem;
+
+    fn layer(&self, inner: FS) -> Self::Filesystem;
+}
+
+pub trait AsyncFilesystemExt: AsyncFilesystem + Sized {
+    /// Wrap this filesystem in `layer`. The last layer added is the outermost one.
+    fn with_layer<L: Layer<Self>>(self, layer: L) -> L::Filesystem {
+        layer.layer(self)
+    }
+}
+
+impl<FS: AsyncFilesystem> AsyncFilesystemExt for FS {}
+
+/// Two layers applied one after the other, `inner` first.
+#[derive(Debug, Clone)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    pub fn new(inner: Inner, outer: Outer) -> Self {
+        Self { inner, outer }
+    }
+}
+
+impl<FS, Inner, Outer> Layer<FS> for Stack<Inner, Outer>
+where
+    FS: AsyncFilesystem,
+    Inner: Layer<FS>,
+    Outer: Layer<Inner::Filesystem>,
+{
+    type Filesystem = Outer::Filesystem;
+
+    fn layer(&self, inner: FS) -> Self::Filesystem {
+        self.outer.layer(self.inner.layer(inner))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Getattr,
+    Setattr,
+    Lookup,
+    Readdir,
+    Readdirplus,
+    Read,
+    Open,
+    Flush,
+    Release,
+    Opendir,
+    Releasedir,
+    Write,
+    Create,
+    Mknod,
+    Mkdir,
+    Unlink,
+    Rmdir,
+    Rename,
+    Readlink,
+    Symlink,
+    Link,
+    Statfs,
+    Access,
+    Getxattr,
+    Listxattr,
+    Setxattr,
+    Removexattr,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Getattr => "getattr",
+            Operation::Setattr => "setattr",
+            Operation::Lookup => "lookup",
+            Operation::Readdir => "readdir",
+            Operation::Readdirplus => "readdirplus",
+            Operation::Read => "read",
+            Operation::Open => "open",
+            Operation::Flush => "flush",
+            Operation::Release => "release",
+            Operation::Opendir => "opendir",
+            Operation::Releasedir => "releasedir",
+            Operation::Write => "write",
+            Operation::Create => "create",
+            Operation::Mknod => "mknod",
+            Operation::Mkdir => "mkdir",
+            Operation::Unlink => "unlink",
+            Operation::Rmdir => "rmdir",
+            Operation::Rename => "rename",
+            Operation::Readlink => "readlink",
+            Operation::Symlink => "symlink",
+            Operation::Link => "link",
+            Operation::Statfs => "statfs",
+            Operation::Access => "access",
+            Operation::Getxattr => "getxattr",
+            Operation::Listxattr => "listxattr",
+            Operation::Setxattr => "setxattr",
+            Operation::Removexattr => "removexattr",
+        }
+    }
+
+    /// Whether dropping the operation midway leaves the filesystem as it was:
+    /// false for operations that change it or release handles, which may have
+    /// taken effect by the time they are dropped.
+    pub fn is_cancellable(&self) -> bool {
+        !matches!(
+            self,
+            Operation::Setattr
+                | Operation::Flush
+                | Operation::Release
+                | Operation::Releasedir
+                | Operation::Write
+                | Operation::Create
+                | Operation::Mknod
+                | Operation::Mkdir
+                | Operation::Unlink
+                | Operation::Rmdir
+                | Operation::Rename
+                | Operation::Symlink
+                | Operation::Link
+                | Operation::Setxattr
+                | Operation::Removexattr
+        )
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The operation being run through a [`Middleware`]: its kind, the request it
+/// answers and the inode it targets (the parent for entry operations).
+#[derive(Debug, Clone, Copy)]
+pub struct Call<'a> {
+    pub op: Operation,
+    pub req: &'a RequestContext,
+    pub ino: u64,
+}
+
+/// Code run around every operation of a [`Layered`] filesystem.
+///
+/// Every type implementing `Middleware + Clone` is a [`Layer`].
+#[async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    async fn call<'a, T, E>(
+        &'a self,
+        call: Call<'a>,
+        fut: BoxFuture<'a, Result<T, E>>,
+    ) -> Result<T, E>
+    where
+        T: Send + 'a,
+        E: fmt::Debug + Send + From<AsyncFilesystemError> + 'a;
+}
+
+impl<FS: AsyncFilesystem, M: Middleware + Clone> Layer<FS> for M {
+    type Filesystem = Layered<FS, M>;
+
+    fn layer(&self, inner: FS) -> Self::Filesystem {
+        Layered {
+            inner,
+            middleware: self.clone(),
+        }
+    }
+}
+
+/// A filesystem whose operations all go through a [`Middleware`].
+pub struct Layered<FS, M> {
+    inner: FS,
+    middleware: M,
+}
+
+impl<FS, M> Layered<FS, M> {
+    pub fn inner(&self) -> &FS {
+        &self.inner
+    }
+
+    pub fn middleware(&self) -> &M {
+        &self.middleware
+    }
+}
+
+#[async_trait]
+impl<FS: AsyncFilesystem, M: Middleware> AsyncFilesystem for Layered<FS, M> {
+    type Error = FS::Error;
+    type Handle = FS::Handle;
+
+    async fn getattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        let call = Call {
+            op: Operation::Getattr,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.getattr(req, ino))
+            .await
+    }
+
+    async fn setattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: Option<&Self::Handle>,
+        attr: SetAttr,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        let call = Call {
+            op: Operation::Setattr,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.setattr(req, ino, fh, attr))
+            .await
+    }
+
+    async fn lookup(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let call = Call {
+            op: Operation::Lookup,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(call, self.inner.lookup(req, parent, name))
+            .await
+    }
+
+    async fn readdir(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error> {
+        let call = Call {
+            op: Operation::Readdir,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.readdir(req, ino, fh, offset))
+            .await
+    }
+
+    async fn readdirplus(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+    ) -> Result<Vec<(i64, String, Duration, FileAttr, u64)>, Self::Error> {
+        let call = Call {
+            op: Operation::Readdirplus,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.readdirplus(req, ino, fh, offset))
+            .await
+    }
+
+    async fn read(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+        size: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<Vec<u8>, Self::Error> {
+        let call = Call {
+            op: Operation::Read,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(
+                call,
+                self.inner.read(req, ino, fh, offset, size, flags, lock),
+            )
+            .await
+    }
+
+    async fn open(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        flags: i32,
+    ) -> Result<(Self::Handle, u32), Self::Error> {
+        let call = Call {
+            op: Operation::Open,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.open(req, ino, flags))
+            .await
+    }
+
+    async fn flush(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        lock_owner: u64,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Flush,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.flush(req, ino, fh, lock_owner))
+            .await
+    }
+
+    async fn release(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        flags: i32,
+        lock: Option<u64>,
+        flush: bool,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Release,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.release(req, ino, fh, flags, lock, flush))
+            .await
+    }
+
+    async fn opendir(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        flags: i32,
+    ) -> Result<(Self::Handle, u32), Self::Error> {
+        let call = Call {
+            op: Operation::Opendir,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.opendir(req, ino, flags))
+            .await
+    }
+
+    async fn releasedir(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        flags: i32,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Releasedir,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.releasedir(req, ino, fh, flags))
+            .await
+    }
+
+    async fn write(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: &Self::Handle,
+        offset: i64,
+        data: &[u8],
+        write_flags: u32,
+        flags: i32,
+        lock: Option<u64>,
+    ) -> Result<u32, Self::Error> {
+        let call = Call {
+            op: Operation::Write,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(
+                call,
+                self.inner
+                    .write(req, ino, fh, offset, data, write_flags, flags, lock),
+            )
+            .await
+    }
+
+    async fn create(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+        flags: i32,
+    ) -> Result<(Duration, FileAttr, u64, Self::Handle, u32), Self::Error> {
+        let call = Call {
+            op: Operation::Create,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(
+                call,
+                self.inner.create(req, parent, name, mode, umask, flags),
+            )
+            .await
+    }
+
+    async fn mknod(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+        rdev: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let call = Call {
+            op: Operation::Mknod,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(call, self.inner.mknod(req, parent, name, mode, umask, rdev))
+            .await
+    }
+
+    async fn mkdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let call = Call {
+            op: Operation::Mkdir,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(call, self.inner.mkdir(req, parent, name, mode, umask))
+            .await
+    }
+
+    async fn unlink(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Unlink,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(call, self.inner.unlink(req, parent, name))
+            .await
+    }
+
+    async fn rmdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Rmdir,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(call, self.inner.rmdir(req, parent, name))
+            .await
+    }
+
+    async fn rename(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+        flags: u32,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Rename,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(
+                call,
+                self.inner
+                    .rename(req, parent, name, newparent, newname, flags),
+            )
+            .await
+    }
+
+    async fn readlink(&self, req: &RequestContext, ino: u64) -> Result<Vec<u8>, Self::Error> {
+        let call = Call {
+            op: Operation::Readlink,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.readlink(req, ino))
+            .await
+    }
+
+    async fn symlink(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        link: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let call = Call {
+            op: Operation::Symlink,
+            req,
+            ino: parent,
+        };
+        self.middleware
+            .call(call, self.inner.symlink(req, parent, name, link))
+            .await
+    }
+
+    async fn link(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        newparent: u64,
+        newname: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        let call = Call {
+            op: Operation::Link,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.link(req, ino, newparent, newname))
+            .await
+    }
+
+    async fn statfs(&self, req: &RequestContext, ino: u64) -> Result<Statfs, Self::Error> {
+        let call = Call {
+            op: Operation::Statfs,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.statfs(req, ino))
+            .await
+    }
+
+    async fn access(&self, req: &RequestContext, ino: u64, mask: i32) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Access,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.access(req, ino, mask))
+            .await
+    }
+
+    async fn getxattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> Result<Vec<u8>, Self::Error> {
+        let call = Call {
+            op: Operation::Getxattr,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.getxattr(req, ino, name))
+            .await
+    }
+
+    async fn listxattr(&self, req: &RequestContext, ino: u64) -> Result<Vec<String>, Self::Error> {
+        let call = Call {
+            op: Operation::Listxattr,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.listxattr(req, ino))
+            .await
+    }
+
+    async fn setxattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        name: &str,
+        value: &[u8],
+        flags: i32,
+        position: u32,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Setxattr,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(
+                call,
+                self.inner.setxattr(req, ino, name, value, flags, position),
+            )
+            .await
+    }
+
+    async fn removexattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        let call = Call {
+            op: Operation::Removexattr,
+            req,
+            ino,
+        };
+        self.middleware
+            .call(call, self.inner.removexattr(req, ino, name))
+            .await
+    }
+}
+
+/// Logs every operation with its caller, duration and outcome.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TraceLayer;
+
+#[async_trait]
+impl Middleware for TraceLayer {
+    async fn call<'a, T, E>(
+        &'a self,
+        call: Call<'a>,
+        fut: BoxFuture<'a, Result<T, E>>,
+    ) -> Result<T, E>
+    where
+        T: Send + 'a,
+        E: fmt::Debug + Send + From<AsyncFilesystemError> + 'a,
+    {
+        let start = Instant::now();
+        let r = fut.await;
+
+        match &r {
+            Ok(_) => debug!(
+                "{}({}) #{} uid={} pid={}: ok in {:?}",
+                call.op,
+                call.ino,
+                call.req.unique,
+                call.req.uid,
+                call.req.pid,
+                start.elapsed()
+            ),
+            Err(e) => debug!(
+                "{}({}) #{} uid={} pid={}: {:?} in {:?}",
+                call.op,
+                call.ino,
+                call.req.unique,
+                call.req.uid,
+                call.req.pid,
+                e,
+                start.elapsed()
+            ),
+        }
+
+        r
+    }
+}
+
+/// Upper bounds of the latency histogram buckets; a last bucket counts the rest.
+pub const LATENCY_BUCKETS: [Duration; 12] = [
+    Duration::from_micros(50),
+    Duration::from_micros(100),
+    Duration::from_micros(250),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    errors: AtomicU64,
+    total_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, elapsed: Duration, failed: bool) {
+        let i = LATENCY_BUCKETS
+            .iter()
+            .position(|b| elapsed <= *b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        self.total_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if failed {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+            errors: self.errors.load(Ordering::Relaxed),
+            total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Latencies recorded for one operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencySnapshot {
+    /// Counts per bucket of [`LATENCY_BUCKETS`], plus one for slower calls.
+    pub buckets: Vec<u64>,
+    pub errors: u64,
+    pub total: Duration,
+}
+
+impl LatencySnapshot {
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            n => Some(self.total / n as u32),
+        }
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, `None` if it is in
+    /// the overflow bucket or nothing was recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let rank = (q.clamp(0.0, 1.0) * self.count() as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return LATENCY_BUCKETS.get(i).copied();
+            }
+        }
+
+        None
+    }
+}
+
+/// Records a latency histogram per operation. Clones share the same histograms.
+#[derive(Clone, Default)]
+pub struct MetricsLayer {
+    histograms: Arc<std::sync::Mutex<HashMap<Operation, Arc<Histogram>>>>,
+}
+
+impl MetricsLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> HashMap<Operation, LatencySnapshot> {
+        self.histograms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(op, h)| (*op, h.snapshot()))
+            .collect()
+    }
+
+    fn histogram(&self, op: Operation) -> Arc<Histogram> {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(op)
+            .or_default()
+            .clone()
+    }
+}
+
+#[async_trait]
+impl Middleware for MetricsLayer {
+    async fn call<'a, T, E>(
+        &'a self,
+        call: Call<'a>,
+        fut: BoxFuture<'a, Result<T, E>>,
+    ) -> Result<T, E>
+    where
+        T: Send + 'a,
+        E: fmt::Debug + Send + From<AsyncFilesystemError> + 'a,
+    {
+        let start = Instant::now();
+        let r = fut.await;
+
+        self.histogram(call.op).record(start.elapsed(), r.is_err());
+
+        r
+    }
+}
+
+/// Fails operations that take longer than their timeout with `ETIMEDOUT`.
+///
+/// The default timeout only applies to [cancellable](Operation::is_cancellable)
+/// operations: the others run to completion unless given a timeout of their own
+/// with [`with_timeout`](Self::with_timeout).
+#[derive(Debug, Clone)]
+pub struct TimeoutLayer {
+    default: Duration,
+    overrides: HashMap<Operation, Duration>,
+}
+
+impl TimeoutLayer {
+    pub fn new(default: Duration) -> Self {
+        Self {
+            default,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Use a specific timeout for `op`, even if it is not cancellable.
+    pub fn with_timeout(mut self, op: Operation, timeout: Duration) -> Self {
+        self.overrides.insert(op, timeout);
+        self
+    }
+
+    fn timeout(&self, op: Operation) -> Option<Duration> {
+        match self.overrides.get(&op) {
+            Some(timeout) => Some(*timeout),
+            None => op.is_cancellable().then_some(self.default),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for TimeoutLayer {
+    async fn call<'a, T, E>(
+        &'a self,
+        call: Call<'a>,
+        fut: BoxFuture<'a, Result<T, E>>,
+    ) -> Result<T, E>
+    where
+        T: Send + 'a,
+        E: fmt::Debug + Send + From<AsyncFilesystemError> + 'a,
+    {
+        let Some(timeout) = self.timeout(call.op) else {
+            return fut.await;
+        };
+
+        match tokio::time::timeout(timeout, fut).await {
+            Ok(r) => r,
+            Err(_) => {
+                warn!("{}({}) timed out after {:?}", call.op, call.ino, timeout);
+                Err(AsyncFilesystemError::TimedOut(call.op.name(), timeout).into())
+            }
+        }
+    }
+}
+
+/// Limits how many operations run at the same time; the others wait for a slot.
+/// Clones share the same limit.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitLayer {
+    semaphore: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimitLayer {
+    pub fn new(max: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for ConcurrencyLimitLayer {
+    async fn call<'a, T, E>(
+        &'a self,
+        _call: Call<'a>,
+        fut: BoxFuture<'a, Result<T, E>>,
+    ) -> Result<T, E>
+    where
+        T: Send + 'a,
+        E: fmt::Debug + Send + From<AsyncFilesystemError> + 'a,
+    {
+        let _permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("the concurrency limit is never closed");
+
+        fut.await
+    }
+}
+
+/// Wraps a filesystem in a [`CachingFilesystem`].
+#[derive(Debug, Clone, Default)]
+pub struct CacheLayer {
+    capacity: Option<usize>,
+    readdir_ttl: Option<Duration>,
+}
+
+impl CacheLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn with_readdir_ttl(mut self, ttl: Duration) -> Self {
+        self.readdir_ttl = Some(ttl);
+        self
+    }
+}
+
+impl<FS: AsyncFilesystem> Layer<FS> for CacheLayer {
+    type Filesystem = CachingFilesystem<FS>;
+
+    fn layer(&self, inner: FS) -> Self::Filesystem {
+        let mut fs = CachingFilesystem::new(inner);
+        if let Some(capacity) = self.capacity {
+            fs = fs.with_capacity(capacity);
+        }
+        if let Some(ttl) = self.readdir_ttl {
+            fs = fs.with_readdir_ttl(ttl);
+        }
+        fs
+    }
+}
@@ -1,5 +1,10 @@
 pub mod async_filesystem;
+pub mod cache;
 pub mod errors;
+mod handles;
+pub mod layer;
 pub mod mount;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use fuser;
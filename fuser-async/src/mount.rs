@@ -1,30 +1,191 @@
-use std::{future::Future, path::Path};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use fuser::MountOption;
-use log::info;
-use tokio::runtime::Handle;
+use fuser::{BackgroundSession, MountOption};
+use log::{info, warn};
+use tokio::{runtime::Handle, sync::Notify, task};
 
 use crate::{
     async_filesystem::{AsyncFilesystem, AsyncFsImpl},
     errors::AsyncFilesystemError,
 };
 
-pub fn spawn_mount<'a, FS: AsyncFilesystem + Send + 'static + 'a, P: AsRef<Path>>(
+/// Counts the requests that have been spawned but not yet answered.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    count: AtomicUsize,
+    closed: AtomicBool,
+    idle: Notify,
+}
+
+pub(crate) struct InFlightGuard(Arc<InFlight>);
+
+impl InFlight {
+    /// Track a new request, or `None` once the gate is closed.
+    pub fn enter(self: &Arc<Self>) -> Option<InFlightGuard> {
+        // Count the request before checking the gate, so that `drain` either
+        // waits for it or `enter` sees the gate closed.
+        self.count.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self.clone());
+
+        (!self.closed.load(Ordering::SeqCst)).then_some(guard)
+    }
+
+    /// Refuse the requests arriving from now on.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    /// Wait until every request spawned so far has been answered.
+    pub async fn drain(&self) {
+        loop {
+            let idle = self.idle.notified();
+            if self.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// A mounted [`AsyncFilesystem`].
+///
+/// Dropping the handle unmounts the filesystem without waiting for in-flight
+/// requests; use [`MountHandle::unmount`] for a graceful shutdown.
+///
+/// There is no way to push cache invalidations to the kernel yet: fuser 0.12
+/// keeps the FUSE channel private and has no notify API, so the kernel keeps
+/// attributes and entries until the TTL returned with them expires. Backends
+/// whose data changes should return short TTLs, and report changes to a
+/// [`crate::cache::CachingFilesystem`] through its `invalidate*` methods.
+pub struct MountHandle {
+    mountpoint: PathBuf,
+    session: Option<BackgroundSession>,
+    in_flight: Arc<InFlight>,
+}
+
+impl MountHandle {
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Whether the session thread is still serving kernel requests.
+    pub fn is_alive(&self) -> bool {
+        self.session
+            .as_ref()
+            .map(|s| !s.guard.is_finished())
+            .unwrap_or(false)
+    }
+
+    /// Wait for in-flight requests to be answered, then unmount the filesystem
+    /// and wait for the session thread to exit.
+    ///
+    /// Requests arriving while the in-flight ones drain are answered with `EIO`.
+    pub async fn unmount(mut self) -> Result<(), AsyncFilesystemError> {
+        info!("Unmounting {}...", self.mountpoint.display());
+
+        self.in_flight.close();
+        self.in_flight.drain().await;
+
+        if let Some(session) = self.session.take() {
+            // Dropping the rest of the session unmounts the filesystem, which ends
+            // the session loop.
+            let guard = {
+                let session = session;
+                session.guard
+            };
+
+            task::spawn_blocking(move || guard.join())
+                .await
+                .map_err(|e| AsyncFilesystemError::UnmountError(e.to_string()))?
+                .map_err(|_| {
+                    AsyncFilesystemError::UnmountError("session panicked".to_string())
+                })??;
+        }
+
+        info!("Unmounted {}", self.mountpoint.display());
+
+        Ok(())
+    }
+}
+
+impl Drop for MountHandle {
+    fn drop(&mut self) {
+        if self.session.take().is_some() {
+            warn!(
+                "Mount handle dropped, unmounting {}",
+                self.mountpoint.display()
+            );
+        }
+    }
+}
+
+pub fn spawn_mount<FS: AsyncFilesystem, P: AsRef<Path>>(
     filesystem: FS,
     mountpoint: P,
     options: &[MountOption],
-) -> Result<impl Future<Output = ()>, AsyncFilesystemError> {
+) -> Result<MountHandle, AsyncFilesystemError> {
     // check_option_conflicts(options)?;
-    let afs = AsyncFsImpl::new(filesystem, Handle::current());
+    let in_flight = Arc::new(InFlight::default());
+    let afs = AsyncFsImpl::new(Arc::new(filesystem), Handle::current(), in_flight.clone());
+
+    let session =
+        fuser::spawn_mount2(afs, mountpoint, options).map_err(AsyncFilesystemError::MountError)?;
+
+    Ok(MountHandle {
+        mountpoint: session.mountpoint.clone(),
+        session: Some(session),
+        in_flight,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn unmount_waits_for_slow_requests_and_refuses_new_ones() {
+        let in_flight = Arc::new(InFlight::default());
+        let handle = MountHandle {
+            mountpoint: PathBuf::from("/mnt"),
+            session: None,
+            in_flight: in_flight.clone(),
+        };
 
-    let bs = fuser::spawn_mount2(afs, mountpoint, options)
-        .map_err(|e| AsyncFilesystemError::MountError(e))?;
+        let (answer, mut answered) = oneshot::channel();
+        let guard = in_flight.enter().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            answer.send(()).unwrap();
+            drop(guard);
+        });
 
-    let umount = async move {
-        info!("Unmounting...");
-        bs.join();
-        info!("Unmounted");
-    };
+        let unmount = tokio::spawn(handle.unmount());
+        while !in_flight.closed.load(Ordering::SeqCst) {
+            tokio::task::yield_now().await;
+        }
+        assert!(in_flight.enter().is_none());
+        assert!(!unmount.is_finished());
 
-    Ok(umount)
+        unmount.await.unwrap().unwrap();
+        answered.try_recv().unwrap();
+        assert_eq!(in_flight.count.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,297 @@
+//! Drive an [`AsyncFilesystem`] in-process, the way the kernel would, without
+//! mounting it.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use fuser::{FileAttr, FileType};
+
+use crate::{
+    async_filesystem::{xattr_list, AsyncFilesystem, RequestContext, XattrReply},
+    errors::ToErrno,
+};
+
+pub const ROOT_INO: u64 = 1;
+
+/// A directory entry as returned by `readdir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub ino: u64,
+    pub offset: i64,
+    pub kind: FileType,
+    pub name: String,
+}
+
+pub struct TestDriver<FS: AsyncFilesystem> {
+    fs: Arc<FS>,
+    uid: u32,
+    gid: u32,
+    pid: u32,
+    unique: AtomicU64,
+    readdir_page: usize,
+    read_chunk: u32,
+}
+
+impl<FS: AsyncFilesystem> TestDriver<FS> {
+    pub fn new(fs: FS) -> Self {
+        Self::from_arc(Arc::new(fs))
+    }
+
+    pub fn from_arc(fs: Arc<FS>) -> Self {
+        Self {
+            fs,
+            uid: 0,
+            gid: 0,
+            pid: std::process::id(),
+            unique: AtomicU64::new(1),
+            readdir_page: 16,
+            read_chunk: 4096,
+        }
+    }
+
+    /// Issue requests as the given user.
+    pub fn with_user(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Number of entries accepted from each `readdir` reply, as if the kernel
+    /// buffer filled up. The rest is fetched again from the last accepted offset.
+    pub fn with_readdir_page(mut self, entries: usize) -> Self {
+        self.readdir_page = entries.max(1);
+        self
+    }
+
+    /// Size of each `read` request.
+    pub fn with_read_chunk(mut self, size: u32) -> Self {
+        self.read_chunk = size.max(1);
+        self
+    }
+
+    pub fn fs(&self) -> &Arc<FS> {
+        &self.fs
+    }
+
+    /// A fresh request context, with a new unique id.
+    pub fn request(&self) -> RequestContext {
+        RequestContext {
+            unique: self.unique.fetch_add(1, Ordering::Relaxed),
+            uid: self.uid,
+            gid: self.gid,
+            pid: self.pid,
+        }
+    }
+
+    pub async fn getattr(&self, ino: u64) -> Result<FileAttr, FS::Error> {
+        self.fs
+            .getattr(&self.request(), ino)
+            .await
+            .map(|(_, attr)| attr)
+    }
+
+    pub async fn lookup(&self, parent: u64, name: &str) -> Result<FileAttr, FS::Error> {
+        self.fs
+            .lookup(&self.request(), parent, name)
+            .await
+            .map(|(_, attr, _)| attr)
+    }
+
+    /// Follow an absolute path from the root through `lookup`.
+    pub async fn resolve(&self, path: &str) -> Result<FileAttr, FS::Error> {
+        let mut attr = self.getattr(ROOT_INO).await?;
+
+        for name in path.split('/').filter(|c| !c.is_empty()) {
+            attr = self.lookup(attr.ino, name).await?;
+        }
+
+        Ok(attr)
+    }
+
+    /// Open a directory, page through `readdir` and release it.
+    ///
+    /// Panics if the offsets returned by the filesystem do not strictly increase,
+    /// since the kernel would loop forever on such a directory.
+    pub async fn readdir(&self, ino: u64) -> Result<Vec<DirEntry>, FS::Error> {
+        let (handle, _) = self.fs.opendir(&self.request(), ino, 0).await?;
+
+        let mut entries: Vec<DirEntry> = Vec::new();
+        let mut offset = 0;
+
+        let r = loop {
+            let page = match self.fs.readdir(&self.request(), ino, &handle, offset).await {
+                Ok(page) => page,
+                Err(e) => break Err(e),
+            };
+
+            if page.is_empty() {
+                break Ok(());
+            }
+
+            for (ino, o, kind, name) in page.into_iter().take(self.readdir_page) {
+                assert!(
+                    o > offset,
+                    "readdir offset {} for {:?} does not advance past {}",
+                    o,
+                    name,
+                    offset
+                );
+                offset = o;
+                entries.push(DirEntry {
+                    ino,
+                    offset: o,
+                    kind,
+                    name,
+                });
+            }
+        };
+
+        self.fs.releasedir(&self.request(), ino, &handle, 0).await?;
+
+        r.map(|()| entries)
+    }
+
+    /// Open a file, read it in chunks until a short read and release it.
+    pub async fn read(&self, ino: u64) -> Result<Vec<u8>, FS::Error> {
+        let (handle, _) = self.fs.open(&self.request(), ino, libc::O_RDONLY).await?;
+
+        let mut content = Vec::new();
+
+        let r = loop {
+            let chunk = match self
+                .fs
+                .read(
+                    &self.request(),
+                    ino,
+                    &handle,
+                    content.len() as i64,
+                    self.read_chunk,
+                    libc::O_RDONLY,
+                    None,
+                )
+                .await
+            {
+                Ok(chunk) => chunk,
+                Err(e) => break Err(e),
+            };
+
+            assert!(
+                chunk.len() <= self.read_chunk as usize,
+                "read returned {} bytes for a {} bytes request",
+                chunk.len(),
+                self.read_chunk
+            );
+
+            let short = chunk.len() < self.read_chunk as usize;
+            content.extend(chunk);
+
+            if short {
+                break Ok(());
+            }
+        };
+
+        self.fs
+            .release(&self.request(), ino, &handle, libc::O_RDONLY, None, false)
+            .await?;
+
+        r.map(|()| content)
+    }
+
+    /// `getxattr` with a buffer of `size` bytes, answered as the kernel would be.
+    pub async fn getxattr(
+        &self,
+        ino: u64,
+        name: &str,
+        size: u32,
+    ) -> Result<XattrReply, libc::c_int> {
+        let value = self
+            .fs
+            .getxattr(&self.request(), ino, name)
+            .await
+            .map_err(|e| e.errno())?;
+
+        XattrReply::new(size, value)
+    }
+
+    /// `listxattr` with a buffer of `size` bytes, answered as the kernel would be.
+    pub async fn listxattr(&self, ino: u64, size: u32) -> Result<XattrReply, libc::c_int> {
+        let names = self
+            .fs
+            .listxattr(&self.request(), ino)
+            .await
+            .map_err(|e| e.errno())?;
+
+        XattrReply::new(size, xattr_list(names))
+    }
+
+    pub async fn read_path(&self, path: &str) -> Result<Vec<u8>, FS::Error> {
+        let attr = self.resolve(path).await?;
+        self.read(attr.ino).await
+    }
+
+    pub async fn assert_exists(&self, path: &str) -> FileAttr {
+        match self.resolve(path).await {
+            Ok(attr) => attr,
+            Err(e) => panic!("expected {} to exist, got {:?}", path, e),
+        }
+    }
+
+    pub async fn assert_errno(&self, path: &str, errno: libc::c_int) {
+        match self.resolve(path).await {
+            Ok(attr) => panic!("expected {} to fail with {}, got {:?}", path, errno, attr),
+            Err(e) => assert_eq!(e.errno(), errno, "unexpected error for {}: {:?}", path, e),
+        }
+    }
+
+    pub async fn assert_not_found(&self, path: &str) {
+        self.assert_errno(path, libc::ENOENT).await
+    }
+
+    /// Assert the names listed in a directory, ignoring `.` and `..`.
+    pub async fn assert_dir_entries<S: AsRef<str> + fmt::Debug>(&self, path: &str, expected: &[S]) {
+        let attr = self.assert_exists(path).await;
+        assert_eq!(
+            attr.kind,
+            FileType::Directory,
+            "{} is not a directory",
+            path
+        );
+
+        let mut names: Vec<String> = match self.readdir(attr.ino).await {
+            Ok(entries) => entries
+                .into_iter()
+                .map(|e| e.name)
+                .filter(|n| n != "." && n != "..")
+                .collect(),
+            Err(e) => panic!("readdir({}) failed: {:?}", path, e),
+        };
+        names.sort();
+
+        let mut expected: Vec<&str> = expected.iter().map(AsRef::as_ref).collect();
+        expected.sort();
+
+        assert_eq!(names, expected, "unexpected entries in {}", path);
+    }
+
+    pub async fn assert_content(&self, path: &str, expected: impl AsRef<[u8]>) {
+        let attr = self.assert_exists(path).await;
+
+        match self.read(attr.ino).await {
+            Ok(content) => {
+                assert_eq!(content, expected.as_ref(), "unexpected content in {}", path);
+                assert_eq!(
+                    attr.size,
+                    content.len() as u64,
+                    "size of {} does not match its content",
+                    path
+                );
+            }
+            Err(e) => panic!("read({}) failed: {:?}", path, e),
+        }
+    }
+}
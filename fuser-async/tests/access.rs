@@ -0,0 +1,98 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::{Child, Command},
+    time::UNIX_EPOCH,
+};
+
+use fuser::{FileAttr, FileType};
+use fuser_async::async_filesystem::RequestContext;
+
+const OWNER: u32 = 501;
+const GROUP: u32 = 20;
+const OTHER: u32 = 1000;
+
+fn attr(perm: u16) -> FileAttr {
+    FileAttr {
+        ino: 2,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileType::RegularFile,
+        perm,
+        nlink: 1,
+        uid: OWNER,
+        gid: GROUP,
+        rdev: 0,
+        flags: 0,
+        blksize: 512,
+    }
+}
+
+fn request(uid: u32, gid: u32, pid: u32) -> RequestContext {
+    RequestContext {
+        unique: 0,
+        uid,
+        gid,
+        pid,
+    }
+}
+
+/// A process in the supplementary group `GROUP`, or `None` if this one can't set
+/// the groups of its children.
+fn process_in_group() -> Option<Child> {
+    let mut command = Command::new("sleep");
+    command.arg("10");
+    // Only async-signal-safe calls between fork and exec.
+    unsafe {
+        command.pre_exec(|| match libc::setgroups(1, &GROUP) {
+            0 => Ok(()),
+            _ => Err(std::io::Error::last_os_error()),
+        });
+    }
+
+    command.spawn().ok()
+}
+
+#[test]
+fn checks_the_bits_of_the_caller() {
+    // No process has pid 0, so the callers have no supplementary groups.
+    let owner = request(OWNER, OTHER, 0);
+    let group = request(OTHER, GROUP, 0);
+    let other = request(OTHER, OTHER, 0);
+    let root = request(0, 0, 0);
+
+    let file = attr(0o640);
+    assert!(owner.can_access(&file, libc::R_OK | libc::W_OK));
+    assert!(group.can_access(&file, libc::R_OK));
+    assert!(!group.can_access(&file, libc::W_OK));
+    assert!(!other.can_access(&file, libc::R_OK));
+    assert!(other.can_access(&file, libc::F_OK));
+
+    assert!(root.can_access(&attr(0), libc::R_OK | libc::W_OK));
+    assert!(!root.can_access(&attr(0o644), libc::X_OK));
+    assert!(root.can_access(&attr(0o744), libc::X_OK));
+}
+
+#[test]
+fn checks_supplementary_groups() {
+    let Some(mut child) = process_in_group() else {
+        eprintln!("skipped: can't set the groups of a child process");
+        return;
+    };
+    let member = request(OTHER, OTHER, child.id());
+
+    assert_eq!(member.groups(), [GROUP]);
+    assert!(member.can_access(&attr(0o640), libc::R_OK));
+    // The group bits apply to members even when the other bits allow more.
+    assert!(!member.can_access(&attr(0o604), libc::R_OK));
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // The groups of exited processes are unknown.
+    assert!(member.groups().is_empty());
+    assert!(!member.can_access(&attr(0o640), libc::R_OK));
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+use fuser_async::{
+    async_filesystem::{AsyncFilesystem, RequestContext, SetAttr},
+    cache::CachingFilesystem,
+    errors::{AsyncFilesystemError, ToErrno},
+};
+use tokio::sync::Notify;
+
+const TTL: Duration = Duration::from_secs(60);
+
+fn attr(ino: u64) -> FileAttr {
+    FileAttr {
+        ino,
+        size: 0,
+        blocks: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind: FileType::RegularFile,
+        perm: 0o644,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        flags: 0,
+        blksize: 512,
+    }
+}
+
+fn request() -> RequestContext {
+    RequestContext {
+        unique: 0,
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    }
+}
+
+/// Files of the root directory, whose `unlink`, `rmdir` and `setattr` wait for
+/// `changing` to be notified before applying the change.
+#[derive(Default)]
+struct Files {
+    names: Mutex<HashMap<String, u64>>,
+    sizes: Mutex<HashMap<u64, u64>>,
+    changing: Notify,
+}
+
+impl Files {
+    fn with_files(n: u64) -> Self {
+        let files = Self::default();
+        files
+            .names
+            .lock()
+            .unwrap()
+            .extend((0..n).map(|i| (format!("{i}.txt"), i + 2)));
+        files
+    }
+}
+
+#[async_trait]
+impl AsyncFilesystem for Files {
+    type Error = AsyncFilesystemError;
+    type Handle = ();
+
+    async fn getattr(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        let size = self.sizes.lock().unwrap().get(&ino).copied().unwrap_or(0);
+        Ok((TTL, FileAttr { size, ..attr(ino) }))
+    }
+
+    async fn setattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        _fh: Option<&Self::Handle>,
+        attr: SetAttr,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        self.changing.notified().await;
+        if let Some(size) = attr.size {
+            self.sizes.lock().unwrap().insert(ino, size);
+        }
+        self.getattr(req, ino).await
+    }
+
+    async fn lookup(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        name: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        match self.names.lock().unwrap().get(name) {
+            Some(ino) => Ok((TTL, attr(*ino), 0)),
+            None => Err(AsyncFilesystemError::GetAttrError(0, name.to_owned())),
+        }
+    }
+
+    async fn readdir(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error> {
+        let mut names: Vec<_> = self.names.lock().unwrap().clone().into_iter().collect();
+        names.sort();
+
+        Ok(names
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, ino))| (ino, i as i64 + 1, FileType::RegularFile, name))
+            .skip(offset as usize)
+            .collect())
+    }
+
+    async fn read(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _offset: i64,
+        _size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+    ) -> Result<Vec<u8>, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented)
+    }
+
+    async fn unlink(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        self.changing.notified().await;
+        self.names.lock().unwrap().remove(name);
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        self.unlink(req, parent, name).await
+    }
+}
+
+#[tokio::test]
+async fn evicts_at_capacity() {
+    let fs = CachingFilesystem::new(Files::with_files(100)).with_capacity(10);
+
+    for i in 0..100 {
+        fs.lookup(&request(), 1, &format!("{i}.txt")).await.unwrap();
+    }
+
+    // Entries and attributes, each bounded by the capacity.
+    assert_eq!(fs.len(), 20);
+    assert_eq!(fs.stats().evictions, 180);
+
+    // The most recent entries are kept.
+    fs.lookup(&request(), 1, "99.txt").await.unwrap();
+    assert_eq!(fs.stats().lookup_hits, 1);
+    fs.lookup(&request(), 1, "0.txt").await.unwrap();
+    assert_eq!(fs.stats().lookup_misses, 101);
+}
+
+#[tokio::test]
+async fn unlink_forgets_entries_cached_while_removing() {
+    let fs = CachingFilesystem::new(Files::with_files(1));
+    let req = request();
+
+    let (unlinked, ()) = tokio::join!(fs.unlink(&req, 1, "0.txt"), async {
+        // Cached again after the unlink started, before the inner removal.
+        fs.lookup(&req, 1, "0.txt").await.unwrap();
+        fs.inner().changing.notify_one();
+    });
+    unlinked.unwrap();
+
+    let e = fs.lookup(&request(), 1, "0.txt").await.unwrap_err();
+    assert_eq!(e.errno(), libc::ENOENT);
+}
+
+#[tokio::test]
+async fn setattr_forgets_attributes_cached_while_changing() {
+    let fs = CachingFilesystem::new(Files::with_files(1));
+    let req = request();
+    let truncate = SetAttr {
+        size: Some(5),
+        ..Default::default()
+    };
+
+    fs.getattr(&req, 2).await.unwrap();
+
+    let (changed, ()) = tokio::join!(fs.setattr(&req, 2, None, truncate), async {
+        // Forgotten when the setattr started, and cached again before the inner
+        // change.
+        assert_eq!(fs.getattr(&req, 2).await.unwrap().1.size, 0);
+        assert_eq!(fs.stats().getattr_misses, 2);
+        fs.inner().changing.notify_one();
+    });
+    assert_eq!(changed.unwrap().1.size, 5);
+
+    assert_eq!(fs.getattr(&req, 2).await.unwrap().1.size, 5);
+}
+
+#[tokio::test]
+async fn invalidates_a_single_inode() {
+    let fs = CachingFilesystem::new(Files::with_files(3));
+
+    for i in 0..3 {
+        fs.lookup(&request(), 1, &format!("{i}.txt")).await.unwrap();
+    }
+    assert_eq!(fs.len(), 6);
+
+    // The entry and the attributes of 1.txt.
+    fs.invalidate(3);
+    assert_eq!(fs.len(), 4);
+    fs.lookup(&request(), 1, "0.txt").await.unwrap();
+    fs.lookup(&request(), 1, "1.txt").await.unwrap();
+    assert_eq!(fs.stats().lookup_hits, 1);
+}
+
+#[tokio::test]
+async fn rmdir_forgets_the_listing_of_the_removed_directory() {
+    let fs = CachingFilesystem::new(Files::with_files(2));
+    let req = request();
+
+    fs.readdir(&req, 2, &(), 0).await.unwrap();
+    fs.readdir(&req, 2, &(), 0).await.unwrap();
+    assert_eq!(fs.stats().readdir_hits, 1);
+
+    let (removed, ()) = tokio::join!(fs.rmdir(&req, 1, "0.txt"), async {
+        fs.inner().changing.notify_one();
+    });
+    removed.unwrap();
+
+    fs.readdir(&req, 2, &(), 0).await.unwrap();
+    assert_eq!(fs.stats().readdir_misses, 2);
+}
@@ -0,0 +1,74 @@
+#[path = "../examples/hello/fs.rs"]
+mod hello;
+
+use fuser::FileType;
+use fuser_async::{
+    async_filesystem::AsyncFilesystem,
+    testing::{DirEntry, TestDriver, ROOT_INO},
+};
+use hello::SimpleFS;
+
+#[tokio::test]
+async fn serves_the_hello_example() {
+    let driver = TestDriver::new(SimpleFS {});
+
+    let root = driver.getattr(ROOT_INO).await.unwrap();
+    assert_eq!(root.kind, FileType::Directory);
+
+    let hello = driver.lookup(ROOT_INO, "hello.txt").await.unwrap();
+    assert_eq!(hello.ino, 2);
+    assert_eq!(driver.getattr(hello.ino).await.unwrap(), hello);
+    driver.assert_not_found("/missing.txt").await;
+
+    assert_eq!(
+        driver.readdir(ROOT_INO).await.unwrap(),
+        [
+            DirEntry {
+                ino: 1,
+                offset: 1,
+                kind: FileType::Directory,
+                name: ".".to_string(),
+            },
+            DirEntry {
+                ino: 1,
+                offset: 2,
+                kind: FileType::Directory,
+                name: "..".to_string(),
+            },
+            DirEntry {
+                ino: 2,
+                offset: 3,
+                kind: FileType::RegularFile,
+                name: "hello.txt".to_string(),
+            },
+        ]
+    );
+
+    driver.assert_content("/hello.txt", "Hello uid 0!\n").await;
+}
+
+#[tokio::test]
+async fn greets_each_user() {
+    let driver = TestDriver::new(SimpleFS {})
+        .with_user(1000, 1000)
+        .with_read_chunk(4);
+
+    let hello = driver.assert_exists("/hello.txt").await;
+    assert_eq!(hello.size, "Hello uid 1000!\n".len() as u64);
+    driver
+        .assert_content("/hello.txt", "Hello uid 1000!\n")
+        .await;
+}
+
+#[tokio::test]
+async fn has_room_without_statfs() {
+    let driver = TestDriver::new(SimpleFS {});
+
+    let statfs = driver
+        .fs()
+        .statfs(&driver.request(), ROOT_INO)
+        .await
+        .unwrap();
+    assert!(statfs.bfree > 0 && statfs.bavail > 0);
+    assert!(statfs.bfree <= statfs.blocks);
+}
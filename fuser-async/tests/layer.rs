@@ -0,0 +1,199 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use fuser::{FileAttr, FileType};
+use fuser_async::{
+    async_filesystem::{AsyncFilesystem, RequestContext},
+    errors::{AsyncFilesystemError, ToErrno},
+    layer::{
+        AsyncFilesystemExt, ConcurrencyLimitLayer, MetricsLayer, Operation, Stack, TimeoutLayer,
+        TraceLayer,
+    },
+};
+
+const DELAY: Duration = Duration::from_millis(50);
+const TIMEOUT: Duration = Duration::from_millis(5);
+
+fn request() -> RequestContext {
+    RequestContext {
+        unique: 0,
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    }
+}
+
+/// Answers every operation after `DELAY`, and records whether `unlink` completed
+/// and how many `getattr` calls ran at the same time.
+#[derive(Default)]
+struct Slow {
+    unlinked: AtomicBool,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+#[async_trait]
+impl AsyncFilesystem for Slow {
+    type Error = AsyncFilesystemError;
+    type Handle = ();
+
+    async fn getattr(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(DELAY).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+
+        Ok((
+            Duration::ZERO,
+            FileAttr {
+                ino,
+                size: 0,
+                blocks: 0,
+                atime: UNIX_EPOCH,
+                mtime: UNIX_EPOCH,
+                ctime: UNIX_EPOCH,
+                crtime: UNIX_EPOCH,
+                kind: FileType::Directory,
+                perm: 0o755,
+                nlink: 2,
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                flags: 0,
+                blksize: 512,
+            },
+        ))
+    }
+
+    async fn lookup(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented)
+    }
+
+    async fn readdir(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented)
+    }
+
+    async fn read(
+        &self,
+        _req: &RequestContext,
+        _ino: u64,
+        _fh: &Self::Handle,
+        _offset: i64,
+        _size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+    ) -> Result<Vec<u8>, Self::Error> {
+        Err(AsyncFilesystemError::NotImplemented)
+    }
+
+    async fn unlink(
+        &self,
+        _req: &RequestContext,
+        _parent: u64,
+        _name: &str,
+    ) -> Result<(), Self::Error> {
+        tokio::time::sleep(DELAY).await;
+        self.unlinked.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn times_out_cancellable_operations() {
+    let fs = Slow::default().with_layer(TimeoutLayer::new(TIMEOUT));
+
+    let e = fs.getattr(&request(), 1).await.unwrap_err();
+    assert_eq!(e.errno(), libc::ETIMEDOUT);
+}
+
+#[tokio::test]
+async fn lets_mutating_operations_complete() {
+    let fs = Slow::default().with_layer(TimeoutLayer::new(TIMEOUT));
+
+    fs.unlink(&request(), 1, "a").await.unwrap();
+    assert!(fs.inner().unlinked.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn times_out_operations_given_a_timeout() {
+    let fs = Slow::default()
+        .with_layer(TimeoutLayer::new(TIMEOUT).with_timeout(Operation::Unlink, TIMEOUT));
+
+    let e = fs.unlink(&request(), 1, "a").await.unwrap_err();
+    assert_eq!(e.errno(), libc::ETIMEDOUT);
+    assert!(!fs.inner().unlinked.load(Ordering::Relaxed));
+}
+
+#[tokio::test]
+async fn limits_concurrent_operations() {
+    let fs = Arc::new(Slow::default().with_layer(ConcurrencyLimitLayer::new(2)));
+
+    let tasks: Vec<_> = (0..6)
+        .map(|ino| {
+            let fs = fs.clone();
+            tokio::spawn(async move { fs.getattr(&request(), ino).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+
+    assert_eq!(fs.inner().max_running.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn records_latencies_and_errors() {
+    let metrics = MetricsLayer::new();
+    let fs = Slow::default().with_layer(metrics.clone());
+
+    fs.getattr(&request(), 1).await.unwrap();
+    fs.getattr(&request(), 2).await.unwrap();
+    fs.lookup(&request(), 1, "a").await.unwrap_err();
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.len(), 2);
+
+    let getattr = &snapshot[&Operation::Getattr];
+    assert_eq!((getattr.count(), getattr.errors), (2, 0));
+    assert!(getattr.mean().unwrap() >= DELAY);
+    assert!(getattr.quantile(0.5).unwrap() >= DELAY);
+
+    let lookup = &snapshot[&Operation::Lookup];
+    assert_eq!((lookup.count(), lookup.errors), (1, 1));
+}
+
+#[tokio::test]
+async fn traces_without_changing_results() {
+    let metrics = MetricsLayer::new();
+    let fs = Slow::default().with_layer(Stack::new(TraceLayer, metrics.clone()));
+
+    assert_eq!(fs.getattr(&request(), 7).await.unwrap().1.ino, 7);
+    let e = fs.lookup(&request(), 1, "a").await.unwrap_err();
+    assert_eq!(e.errno(), libc::ENOSYS);
+
+    // The outer layer sees the calls the inner one traced.
+    assert_eq!(metrics.snapshot()[&Operation::Getattr].count(), 1);
+    fs.unlink(&request(), 1, "a").await.unwrap();
+    assert!(fs.inner().inner().unlinked.load(Ordering::Relaxed));
+}
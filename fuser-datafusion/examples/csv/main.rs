@@ -1,5 +1,14 @@
-use datafusion::prelude::*;
-use fuser_async::{fuser::MountOption, mount::spawn_mount};
+use std::time::Duration;
+
+use datafusion::{
+    arrow::datatypes::{DataType, Field, Schema},
+    prelude::*,
+};
+use fuser_async::{
+    fuser::MountOption,
+    layer::{AsyncFilesystemExt, TimeoutLayer, TraceLayer},
+    mount::spawn_mount,
+};
 use fuser_datafusion::{
     helpers::create_context, DatafusionFs, CONTENT_TABLE, METADATA_SCHEMA, METADATA_TABLE,
 };
@@ -17,17 +26,32 @@ use tokio::{
 async fn load_fs() -> datafusion::error::Result<SessionContext> {
     let ctx = create_context();
 
+    // The optional `target` column, for the symlink of the example.
+    let metadata_schema = Schema::new(
+        METADATA_SCHEMA
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .chain([Field::new("target", DataType::Utf8, true)])
+            .collect::<Vec<_>>(),
+    );
+
     ctx.register_csv(
         METADATA_TABLE,
         "fuser-datafusion/examples/data/metadata.csv",
-        CsvReadOptions::default().schema(&METADATA_SCHEMA),
+        CsvReadOptions::default().schema(&metadata_schema),
     )
     .await?;
 
+    // The inodes are unsigned, as in the content table.
+    let content_schema = Schema::new(vec![
+        Field::new("ino", DataType::UInt64, false),
+        Field::new("content", DataType::Utf8, false),
+    ]);
     let content = ctx
         .read_csv(
             "fuser-datafusion/examples/data/content.csv",
-            CsvReadOptions::default(),
+            CsvReadOptions::default().schema(&content_schema),
         )
         .await?;
 
@@ -49,7 +73,10 @@ pub async fn main() -> anyhow::Result<()> {
     pretty_env_logger::init();
 
     let ctx = load_fs().await?;
-    let fs = DatafusionFs::new(ctx);
+    let fs = DatafusionFs::try_new(ctx)
+        .await?
+        .with_layer(TimeoutLayer::new(Duration::from_secs(30)))
+        .with_layer(TraceLayer);
     let mountpoint = tempfile::tempdir().unwrap();
 
     info!("Mounting filesystem at {}", mountpoint.path().display());
@@ -62,28 +89,20 @@ pub async fn main() -> anyhow::Result<()> {
         MountOption::CUSTOM("volname=DatafusionFS".to_string()),
     ];
 
-    let umount = spawn_mount(fs, mountpoint, &options).expect("Failed to mount filesystem");
-
-    // let umount_handle = tokio::spawn(umount);
+    let mount = spawn_mount(fs, mountpoint.path(), &options).expect("Failed to mount filesystem");
 
     let mut sig_term = signal(SignalKind::terminate())?;
 
     select! {
         _ = signal::ctrl_c() => {
-            info!("Received Ctrl-C, sending unmount signals");
-            // stop_sender.send(()).unwrap();
-            // umount_handle.await?;
-            info!("Unmounted filesystem");
+            info!("Received Ctrl-C, unmounting");
         }
         _ = sig_term.recv() => {
-            info!("Received SIGTERM, sending unmount signal");
-            // stop_sender.send(()).unwrap();
-            // umount_handle.await?;
-            info!("Unmounted filesystem");
+            info!("Received SIGTERM, unmounting");
         }
     };
 
-    umount.await;
+    mount.unmount().await?;
 
     Ok(())
 }
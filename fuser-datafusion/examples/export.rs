@@ -0,0 +1,82 @@
+use std::{path::Path, time::Duration};
+
+use datafusion::prelude::*;
+use fuser_async::{
+    fuser::MountOption,
+    layer::{AsyncFilesystemExt, TimeoutLayer, TraceLayer},
+    mount::spawn_mount,
+};
+use fuser_datafusion::{helpers::create_context, TableExportFs};
+
+use log::info;
+
+use tokio::{
+    select,
+    signal::{
+        self,
+        unix::{signal, SignalKind},
+    },
+};
+
+#[tokio::main]
+pub async fn main() -> anyhow::Result<()> {
+    pretty_env_logger::init();
+
+    let paths: Vec<_> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        anyhow::bail!("usage: export <table.csv|table.parquet>...");
+    }
+
+    let ctx = create_context();
+    for path in &paths {
+        let path = Path::new(path);
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            anyhow::bail!("invalid table path {}", path.display());
+        };
+        let location = path.to_string_lossy();
+
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("csv") => {
+                ctx.register_csv(name, &location, CsvReadOptions::default())
+                    .await?
+            }
+            Some("parquet") => {
+                ctx.register_parquet(name, &location, ParquetReadOptions::default())
+                    .await?
+            }
+            _ => anyhow::bail!("unsupported table format {}", path.display()),
+        }
+    }
+
+    let fs = TableExportFs::new(ctx)
+        .with_layer(TimeoutLayer::new(Duration::from_secs(30)))
+        .with_layer(TraceLayer);
+    let mountpoint = tempfile::tempdir().unwrap();
+
+    info!("Mounting filesystem at {}", mountpoint.path().display());
+
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("datafusion-export".to_string()),
+        MountOption::AutoUnmount,
+        MountOption::AllowRoot,
+        MountOption::CUSTOM("volname=DatafusionExport".to_string()),
+    ];
+
+    let mount = spawn_mount(fs, mountpoint.path(), &options).expect("Failed to mount filesystem");
+
+    let mut sig_term = signal(SignalKind::terminate())?;
+
+    select! {
+        _ = signal::ctrl_c() => {
+            info!("Received Ctrl-C, unmounting");
+        }
+        _ = sig_term.recv() => {
+            info!("Received SIGTERM, unmounting");
+        }
+    };
+
+    mount.unmount().await?;
+
+    Ok(())
+}
@@ -1,4 +1,67 @@
+use std::time::Duration;
+
+use fuser_async::{
+    fuser::MountOption,
+    layer::{AsyncFilesystemExt, TimeoutLayer, TraceLayer},
+    mount::spawn_mount,
+};
+use fuser_datafusion::{parquet::ParquetLoader, DatafusionFs};
+
+use log::info;
+
+use tokio::{
+    select,
+    signal::{
+        self,
+        unix::{signal, SignalKind},
+    },
+};
+
 #[tokio::main]
 pub async fn main() -> anyhow::Result<()> {
+    pretty_env_logger::init();
+
+    let mut args = std::env::args().skip(1);
+    let (Some(metadata), Some(content)) = (args.next(), args.next()) else {
+        anyhow::bail!("usage: parquet <metadata> <content> [chunks]");
+    };
+
+    let mut loader = ParquetLoader::new(metadata, content);
+    if let Some(chunks) = args.next() {
+        loader = loader.with_chunks(chunks);
+    }
+
+    let ctx = loader.load().await?;
+    let fs = DatafusionFs::try_new(ctx)
+        .await?
+        .with_layer(TimeoutLayer::new(Duration::from_secs(30)))
+        .with_layer(TraceLayer);
+    let mountpoint = tempfile::tempdir().unwrap();
+
+    info!("Mounting filesystem at {}", mountpoint.path().display());
+
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("datafusion".to_string()),
+        MountOption::AutoUnmount,
+        MountOption::AllowRoot,
+        MountOption::CUSTOM("volname=DatafusionFS".to_string()),
+    ];
+
+    let mount = spawn_mount(fs, mountpoint.path(), &options).expect("Failed to mount filesystem");
+
+    let mut sig_term = signal(SignalKind::terminate())?;
+
+    select! {
+        _ = signal::ctrl_c() => {
+            info!("Received Ctrl-C, unmounting");
+        }
+        _ = sig_term.recv() => {
+            info!("Received SIGTERM, unmounting");
+        }
+    };
+
+    mount.unmount().await?;
+
     Ok(())
 }
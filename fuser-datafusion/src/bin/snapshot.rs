@@ -0,0 +1,40 @@
+use anyhow::{bail, Context};
+use fuser_datafusion::parquet::{snapshot, SnapshotOptions};
+use log::info;
+
+const USAGE: &str = "usage: snapshot [--max-file-size BYTES] [--chunk-size BYTES] <source> <dest>";
+
+fn main() -> anyhow::Result<()> {
+    pretty_env_logger::init();
+
+    let mut options = SnapshotOptions::default();
+    let mut paths = vec![];
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-file-size" => {
+                let size = args.next().context(USAGE)?;
+                options.max_file_size = Some(size.parse().context("invalid --max-file-size")?);
+            }
+            "--chunk-size" => {
+                let size = args.next().context(USAGE)?;
+                options.chunk_size = Some(size.parse().context("invalid --chunk-size")?);
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let [source, dest] = paths.as_slice() else {
+        bail!(USAGE);
+    };
+
+    let stats = snapshot(source, dest, &options)?;
+
+    info!(
+        "Wrote {} entries and {} files ({} bytes) to {}",
+        stats.entries, stats.files, stats.bytes, dest
+    );
+
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use datafusion::{
+    arrow::{csv, datatypes::Schema, json, record_batch::RecordBatch},
+    error::{DataFusionError, Result},
+    logical_expr::LogicalPlan,
+    parquet::arrow::ArrowWriter,
+    prelude::*,
+};
+use fuser_async::errors::AsyncFilesystemError;
+
+use crate::{writable::OpenFile, CONTROL_DIR_INO};
+
+const QUERY_EXTENSION: &str = ".sql";
+const ERROR_EXTENSION: &str = ".err";
+const RESULT_EXTENSIONS: [&str; 3] = [".csv", ".json", ".parquet"];
+
+/// A file of the control directory, owned by the user who created its query.
+#[derive(Clone)]
+pub struct ControlFile {
+    pub ino: u64,
+    pub content: Arc<OpenFile>,
+    pub mtime: SystemTime,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Query files written to the control directory of a
+/// [`DatafusionFs`](crate::DatafusionFs), and the results of running them, see
+/// [`DatafusionFs::with_control_dir`](crate::DatafusionFs::with_control_dir).
+///
+/// Files are kept in memory, and are lost when the filesystem is unmounted.
+pub struct ControlDir {
+    pub name: String,
+    files: Mutex<BTreeMap<String, ControlFile>>,
+    next_ino: AtomicU64,
+}
+
+/// Whether `name` is a query file, the only kind of file that can be created.
+pub fn is_query(name: &str) -> bool {
+    name.len() > QUERY_EXTENSION.len() && name.ends_with(QUERY_EXTENSION)
+}
+
+impl ControlDir {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            files: Mutex::new(BTreeMap::new()),
+            next_ino: AtomicU64::new(CONTROL_DIR_INO + 1),
+        }
+    }
+
+    /// The files of the directory, ordered by name.
+    pub fn entries(&self) -> Vec<(u64, String)> {
+        let files = self.files.lock().unwrap();
+
+        files
+            .iter()
+            .map(|(name, file)| (file.ino, name.clone()))
+            .collect()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u64> {
+        self.files.lock().unwrap().get(name).map(|file| file.ino)
+    }
+
+    /// The name and content of the file `ino`.
+    pub fn file(&self, ino: u64) -> Option<(String, ControlFile)> {
+        let files = self.files.lock().unwrap();
+
+        files
+            .iter()
+            .find(|(_, file)| file.ino == ino)
+            .map(|(name, file)| (name.clone(), file.clone()))
+    }
+
+    /// Create the empty query file `name`, owned by `uid` and `gid`.
+    pub fn create(
+        &self,
+        name: &str,
+        uid: u32,
+        gid: u32,
+    ) -> Result<ControlFile, AsyncFilesystemError> {
+        if !is_query(name) {
+            return Err(AsyncFilesystemError::PermissionDenied(CONTROL_DIR_INO));
+        }
+
+        let mut files = self.files.lock().unwrap();
+
+        if files.contains_key(name) {
+            return Err(AsyncFilesystemError::AlreadyExists(
+                CONTROL_DIR_INO,
+                name.to_owned(),
+            ));
+        }
+
+        let file = ControlFile {
+            ino: self.next_ino.fetch_add(1, Ordering::Relaxed),
+            content: Arc::new(OpenFile::new(vec![])),
+            mtime: SystemTime::now(),
+            uid,
+            gid,
+        };
+        files.insert(name.to_owned(), file.clone());
+
+        Ok(file)
+    }
+
+    /// Remove the file `name`, returning whether it existed.
+    pub fn remove(&self, name: &str) -> bool {
+        self.files.lock().unwrap().remove(name).is_some()
+    }
+
+    pub fn touch(&self, ino: u64, time: SystemTime) {
+        let mut files = self.files.lock().unwrap();
+
+        if let Some(file) = files.values_mut().find(|file| file.ino == ino) {
+            file.mtime = time;
+        }
+    }
+
+    /// Run the query file `ino` against `ctx` if it was written since the last run.
+    ///
+    /// The results of `foo.sql` replace `foo.csv`, `foo.json` and `foo.parquet`,
+    /// or `foo.err` if the query fails.
+    pub async fn run(&self, ctx: &SessionContext, ino: u64) {
+        let Some((name, file)) = self.file(ino) else {
+            return;
+        };
+        let Some(stem) = name.strip_suffix(QUERY_EXTENSION) else {
+            return;
+        };
+        let Some(sql) = file.content.take_dirty() else {
+            return;
+        };
+
+        let results = match String::from_utf8(sql) {
+            Ok(sql) => run_query(ctx, &sql).await,
+            Err(e) => Err(DataFusionError::Execution(e.to_string())),
+        };
+
+        let outputs = match results {
+            Ok(results) => RESULT_EXTENSIONS
+                .into_iter()
+                .zip(results.map(Some))
+                .chain([(ERROR_EXTENSION, None)])
+                .collect::<Vec<_>>(),
+            Err(e) => RESULT_EXTENSIONS
+                .into_iter()
+                .map(|ext| (ext, None))
+                .chain([(ERROR_EXTENSION, Some(format!("{e}\n").into_bytes()))])
+                .collect(),
+        };
+
+        let now = SystemTime::now();
+        let mut files = self.files.lock().unwrap();
+
+        // The query file was removed while running.
+        let Some(query) = files.get_mut(&name).filter(|f| f.ino == ino) else {
+            return;
+        };
+        query.mtime = now;
+        let (uid, gid) = (query.uid, query.gid);
+
+        for (ext, data) in outputs {
+            let output = format!("{stem}{ext}");
+
+            match (data, files.get_mut(&output)) {
+                (Some(data), Some(file)) => {
+                    file.content.replace(data);
+                    file.mtime = now;
+                    (file.uid, file.gid) = (uid, gid);
+                }
+                (Some(data), None) => {
+                    let file = ControlFile {
+                        ino: self.next_ino.fetch_add(1, Ordering::Relaxed),
+                        content: Arc::new(OpenFile::new(data)),
+                        mtime: now,
+                        uid,
+                        gid,
+                    };
+                    files.insert(output, file);
+                }
+                (None, _) => {
+                    files.remove(&output);
+                }
+            }
+        }
+    }
+}
+
+/// The results of `sql` as CSV, JSON and Parquet. Statements that would change
+/// the context are rejected.
+async fn run_query(ctx: &SessionContext, sql: &str) -> Result<[Vec<u8>; 3]> {
+    let plan = ctx.state().create_logical_plan(sql).await?;
+
+    if matches!(
+        plan,
+        LogicalPlan::Ddl(_) | LogicalPlan::Dml(_) | LogicalPlan::Statement(_)
+    ) {
+        return Err(DataFusionError::Plan(
+            "Only queries can be run from the control directory".to_owned(),
+        ));
+    }
+
+    let df = ctx.execute_logical_plan(plan).await?;
+    let schema = Arc::new(Schema::from(df.schema()));
+    let mut batches = df.collect().await?;
+
+    // Still write the CSV header and the Parquet schema.
+    if batches.is_empty() {
+        batches.push(RecordBatch::new_empty(schema.clone()));
+    }
+
+    let mut writer = csv::Writer::new(vec![]);
+    for batch in &batches {
+        writer.write(batch)?;
+    }
+    let csv = writer.into_inner();
+
+    let mut writer = json::ArrayWriter::new(vec![]);
+    writer.write_batches(&batches)?;
+    writer.finish()?;
+    let mut json = writer.into_inner();
+    if json.is_empty() {
+        json.extend_from_slice(b"[]");
+    }
+    json.push(b'\n');
+
+    let mut writer = ArrowWriter::try_new(vec![], schema, None)?;
+    for batch in &batches {
+        writer.write(batch)?;
+    }
+    let parquet = writer.into_inner()?;
+
+    Ok([csv, json, parquet])
+}
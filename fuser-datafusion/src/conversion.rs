@@ -1,14 +1,24 @@
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use datafusion::arrow::{
-    array::{StringArray, UInt64Array},
+    array::{
+        Array, PrimitiveArray, StringArray, TimestampMicrosecondArray, UInt32Array, UInt64Array,
+    },
+    datatypes::ArrowPrimitiveType,
     record_batch::RecordBatch,
 };
 
 use fuser_async::fuser::{FileAttr, FileType};
-use itertools::izip;
 
-use crate::{errors::DatafusionFsError, BinArray};
+use crate::{errors::DatafusionFsError, BinArray, DatafusionFsOptions};
+
+/// Block size reported in file attributes and filesystem statistics.
+pub const BLOCK_SIZE: u64 = 512;
+
+/// Default time to live of attributes and entries. The tables are only expected
+/// to change through the mount, which the kernel keeps track of, except for the
+/// results of query directories.
+pub const TTL: Duration = Duration::from_secs(3600);
 
 pub trait BatchesIterators {
     fn inos(&self, column: usize) -> Box<dyn Iterator<Item = Option<u64>> + '_>;
@@ -17,6 +27,16 @@ pub trait BatchesIterators {
     fn names(&self, column: usize) -> Box<dyn Iterator<Item = Option<&str>> + '_>;
 
     fn content(&self, column: usize) -> Box<dyn Iterator<Item = Option<&[u8]>> + '_>;
+
+    /// Attributes of rows with the metadata columns, a `size` and optionally an
+    /// `nlink` column, found by name.
+    ///
+    /// Missing or null `mode`, `uid` and `gid` fall back to `options`, a missing
+    /// `crtime` to `ctime` and a missing `nlink` to 1.
+    fn file_attrs<'a>(
+        &'a self,
+        options: &'a DatafusionFsOptions,
+    ) -> Box<dyn Iterator<Item = Option<FileAttr>> + 'a>;
 }
 
 impl BatchesIterators for Vec<RecordBatch> {
@@ -65,6 +85,87 @@ impl BatchesIterators for Vec<RecordBatch> {
 
         Box::new(r)
     }
+
+    fn file_attrs<'a>(
+        &'a self,
+        options: &'a DatafusionFsOptions,
+    ) -> Box<dyn Iterator<Item = Option<FileAttr>> + 'a> {
+        let r = self.iter().flat_map(move |batch| {
+            let inos = column::<UInt64Array>(batch, "ino");
+            let kinds = column::<StringArray>(batch, "type");
+            let sizes = column::<UInt64Array>(batch, "size");
+            let targets = column::<StringArray>(batch, "target");
+            let nlinks = column::<UInt64Array>(batch, "nlink");
+            let atimes = column::<TimestampMicrosecondArray>(batch, "atime");
+            let mtimes = column::<TimestampMicrosecondArray>(batch, "mtime");
+            let ctimes = column::<TimestampMicrosecondArray>(batch, "ctime");
+            let crtimes = column::<TimestampMicrosecondArray>(batch, "crtime");
+            let modes = column::<UInt32Array>(batch, "mode");
+            let uids = column::<UInt32Array>(batch, "uid");
+            let gids = column::<UInt32Array>(batch, "gid");
+
+            (0..batch.num_rows()).map(move |i| {
+                let ino = value(inos, i)?;
+                let kind = kinds
+                    .filter(|k| k.is_valid(i))
+                    .and_then(|k| parse_file_type(k.value(i)))?;
+
+                // Symlinks have no content, their size is the length of the target.
+                let size = value(sizes, i)
+                    .or_else(|| {
+                        targets
+                            .filter(|t| t.is_valid(i))
+                            .map(|t| t.value(i).len() as u64)
+                    })
+                    .unwrap_or(0);
+
+                let ctime = value(ctimes, i).map_or(UNIX_EPOCH, to_system_time);
+                let perm = value(modes, i).map_or(
+                    match kind {
+                        FileType::Directory => options.dir_perm,
+                        _ => options.file_perm,
+                    },
+                    |mode| (mode & 0o7777) as u16,
+                );
+
+                Some(FileAttr {
+                    ino,
+                    size,
+                    blocks: size.div_ceil(BLOCK_SIZE),
+                    atime: value(atimes, i).map_or(UNIX_EPOCH, to_system_time),
+                    mtime: value(mtimes, i).map_or(UNIX_EPOCH, to_system_time),
+                    ctime,
+                    crtime: value(crtimes, i).map_or(ctime, to_system_time),
+                    kind,
+                    perm,
+                    nlink: value(nlinks, i).map_or(1, |n| n as u32),
+                    uid: value(uids, i).unwrap_or(options.uid),
+                    gid: value(gids, i).unwrap_or(options.gid),
+                    rdev: 0,
+                    flags: 0,
+                    blksize: BLOCK_SIZE as u32,
+                })
+            })
+        });
+
+        Box::new(r)
+    }
+}
+
+pub fn column<'a, A: 'static>(batch: &'a RecordBatch, name: &str) -> Option<&'a A> {
+    batch.column_by_name(name)?.as_any().downcast_ref::<A>()
+}
+
+fn value<T: ArrowPrimitiveType>(array: Option<&PrimitiveArray<T>>, i: usize) -> Option<T::Native> {
+    array.filter(|a| a.is_valid(i)).map(|a| a.value(i))
+}
+
+fn to_system_time(micros: i64) -> SystemTime {
+    if micros >= 0 {
+        UNIX_EPOCH + Duration::from_micros(micros as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_micros(micros.unsigned_abs())
+    }
 }
 
 fn parse_file_type(s: &str) -> Option<FileType> {
@@ -80,45 +181,36 @@ fn parse_file_type(s: &str) -> Option<FileType> {
     }
 }
 
-pub fn to_file_attr(batches: Vec<RecordBatch>) -> Result<(Duration, FileAttr), DatafusionFsError> {
-    for batch in batches {
-        let inos = batch.column(0).as_any().downcast_ref::<UInt64Array>();
-        let kinds = batch.column(1).as_any().downcast_ref::<StringArray>();
-        let sizes = batch.column(2).as_any().downcast_ref::<UInt64Array>();
-
-        if let (Some(inos), Some(kinds), Some(sizes)) = (inos, kinds, sizes) {
-            for (ino, kind, size) in izip!(inos, kinds, sizes) {
-                if let (Some(ino), Some(kind)) = (ino, kind.and_then(parse_file_type)) {
-                    let size = size.unwrap_or(0);
-                    let blksize = 512;
-                    let blocks = (size + blksize - 1) / blksize;
-
-                    let attr = FileAttr {
-                        ino,
-                        size,
-                        blocks,
-                        atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-                        mtime: UNIX_EPOCH,
-                        ctime: UNIX_EPOCH,
-                        crtime: UNIX_EPOCH,
-                        kind,
-                        perm: match kind {
-                            FileType::Directory => 0o755,
-                            _ => 0o644,
-                        },
-                        nlink: 1,
-                        uid: 501,
-                        gid: 20,
-                        rdev: 0,
-                        flags: 0,
-                        blksize: blksize as u32,
-                    };
-
-                    return Ok((Duration::from_secs(3600), attr));
-                }
-            }
-        }
+/// The `type` of `kind` in the metadata table.
+pub fn file_type_name(kind: FileType) -> &'static str {
+    match kind {
+        FileType::Directory => "Directory",
+        FileType::RegularFile => "RegularFile",
+        FileType::Symlink => "Symlink",
+        FileType::Socket => "Socket",
+        FileType::CharDevice => "CharDevice",
+        FileType::BlockDevice => "BlockDevice",
+        FileType::NamedPipe => "NamePipe",
     }
+}
+
+/// First value of a `UInt64` column, e.g. the result of an aggregate.
+pub fn first_u64(batches: &[RecordBatch], column: usize) -> Option<u64> {
+    batches
+        .iter()
+        .flat_map(|batch| batch.column(column).as_any().downcast_ref::<UInt64Array>())
+        .flat_map(|array| array.iter())
+        .flatten()
+        .next()
+}
+
+/// Build the attributes of the first row of `batches`, see [`BatchesIterators::file_attrs`].
+pub fn to_file_attr(
+    batches: Vec<RecordBatch>,
+    options: &DatafusionFsOptions,
+) -> Result<(Duration, FileAttr), DatafusionFsError> {
+    let attr = batches.file_attrs(options).flatten().next();
 
-    return Err(DatafusionFsError::NotFound);
+    attr.map(|attr| (options.ttl, attr))
+        .ok_or(DatafusionFsError::NotFound)
 }
@@ -1,13 +1,36 @@
+use datafusion::{arrow::datatypes::DataType, error::DataFusionError};
+use fuser_async::errors::{AsyncFilesystemError, ToErrno};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum DatafusionFsError {
     #[error("Datafusion error: {0}")]
-    DatafusionError(#[from] datafusion::error::DataFusionError),
+    DatafusionError(#[from] DataFusionError),
+
+    #[error("Filesystem error: {0}")]
+    FilesystemError(#[from] AsyncFilesystemError),
 
     #[error("Not found")]
     NotFound,
 
     #[error("Not implemented")]
     NotImplemented,
+
+    #[error("Column {1} of table {0} should be {2}")]
+    SchemaMismatch(String, String, DataType),
+}
+
+impl ToErrno for DatafusionFsError {
+    fn errno(&self) -> libc::c_int {
+        match self {
+            DatafusionFsError::DatafusionError(DataFusionError::IoError(e)) => {
+                e.raw_os_error().unwrap_or(libc::EIO)
+            }
+            DatafusionFsError::DatafusionError(_) => libc::EIO,
+            DatafusionFsError::FilesystemError(e) => e.errno(),
+            DatafusionFsError::NotFound => libc::ENOENT,
+            DatafusionFsError::NotImplemented => libc::ENOSYS,
+            DatafusionFsError::SchemaMismatch(_, _, _) => libc::EIO,
+        }
+    }
 }
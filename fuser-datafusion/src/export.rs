@@ -0,0 +1,581 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    os::unix::fs::FileExt,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::{csv, datatypes::Schema, json::LineDelimitedWriter, record_batch::RecordBatch},
+    datasource::TableProvider,
+    error::{DataFusionError, Result as DatafusionResult},
+    parquet::{arrow::ArrowWriter, file::properties::WriterProperties},
+    prelude::*,
+};
+use fuser_async::{
+    async_filesystem::{AsyncFilesystem, RequestContext},
+    errors::AsyncFilesystemError,
+    fuser::{consts::FOPEN_DIRECT_IO, FileAttr, FileType, FUSE_ROOT_ID},
+};
+use futures::StreamExt;
+use log::debug;
+use serde_json::json;
+use tokio::sync::OnceCell;
+
+use crate::{
+    conversion::BLOCK_SIZE, errors::DatafusionFsError, writable::MutableTable, DatafusionFsOptions,
+};
+
+/// Time to live of attributes and entries. Tables can be registered and
+/// deregistered at any time.
+const TTL: Duration = Duration::from_secs(1);
+
+/// Rows per row group of the exported Parquet files.
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+/// The files of a table directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+    Ndjson,
+    Schema,
+}
+
+impl ExportFormat {
+    const ALL: [Self; 4] = [Self::Csv, Self::Parquet, Self::Ndjson, Self::Schema];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Csv => "data.csv",
+            Self::Parquet => "data.parquet",
+            Self::Ndjson => "data.ndjson",
+            Self::Schema => "schema.json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    Root,
+    Catalog(String),
+    Schema(String, String),
+    Table(String, String, String),
+    File(String, String, String, ExportFormat),
+}
+
+impl Node {
+    fn parent(&self) -> Option<Node> {
+        match self {
+            Node::Root => None,
+            Node::Catalog(_) => Some(Node::Root),
+            Node::Schema(c, _) => Some(Node::Catalog(c.clone())),
+            Node::Table(c, s, _) => Some(Node::Schema(c.clone(), s.clone())),
+            Node::File(c, s, t, _) => Some(Node::Table(c.clone(), s.clone(), t.clone())),
+        }
+    }
+}
+
+/// Inodes handed out so far. They are never reused, so a table registered again
+/// under the same name gets its old inodes back.
+#[derive(Default)]
+struct Inodes {
+    nodes: Vec<Node>,
+    inos: HashMap<Node, u64>,
+}
+
+impl Inodes {
+    fn ino(&mut self, node: Node) -> u64 {
+        if let Some(ino) = self.inos.get(&node) {
+            return *ino;
+        }
+
+        self.nodes.push(node.clone());
+        let ino = FUSE_ROOT_ID + self.nodes.len() as u64;
+        self.inos.insert(node, ino);
+
+        ino
+    }
+
+    fn node(&self, ino: u64) -> Option<Node> {
+        match ino {
+            FUSE_ROOT_ID => Some(Node::Root),
+            _ => {
+                let index = ino.checked_sub(FUSE_ROOT_ID + 1)?;
+                self.nodes.get(index as usize).cloned()
+            }
+        }
+    }
+}
+
+/// A read-only filesystem exposing every table registered in a `SessionContext`
+/// as `/<catalog>/<schema>/<table>/`, with the files of [`ExportFormat`].
+///
+/// Files are exported to an anonymous temporary file the first time they are
+/// looked up, a range of record batches at a time, so that they have a size and
+/// can be read in any order, as Parquet readers do starting from the footer.
+/// Concurrent requests wait for the same export, which is kept for as long as the
+/// table is not changed or registered again. Tables whose provider is created for
+/// each lookup, like those of `information_schema`, are exported every time.
+pub struct TableExportFs {
+    ctx: SessionContext,
+    options: DatafusionFsOptions,
+    time: SystemTime,
+    inodes: Mutex<Inodes>,
+    spills: Mutex<HashMap<u64, Arc<SpillSlot>>>,
+}
+
+/// The export of an open file.
+#[derive(Default)]
+pub struct TableExportHandle {
+    spill: Option<Arc<Spill>>,
+}
+
+impl TableExportFs {
+    pub fn new(ctx: SessionContext) -> Self {
+        Self {
+            ctx,
+            options: DatafusionFsOptions::default(),
+            time: SystemTime::now(),
+            inodes: Mutex::default(),
+            spills: Mutex::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: DatafusionFsOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn ino(&self, node: Node) -> u64 {
+        self.inodes.lock().unwrap().ino(node)
+    }
+
+    fn node(&self, ino: u64) -> Result<Node, DatafusionFsError> {
+        self.inodes
+            .lock()
+            .unwrap()
+            .node(ino)
+            .ok_or(DatafusionFsError::NotFound)
+    }
+
+    async fn table(
+        &self,
+        catalog: &str,
+        schema: &str,
+        table: &str,
+    ) -> Result<Arc<dyn TableProvider>, DatafusionFsError> {
+        let schema = self
+            .ctx
+            .catalog(catalog)
+            .and_then(|c| c.schema(schema))
+            .ok_or(DatafusionFsError::NotFound)?;
+
+        schema.table(table).await.ok_or(DatafusionFsError::NotFound)
+    }
+
+    /// The entries of the directory `node`, ordered by name.
+    async fn children(&self, node: &Node) -> Result<Vec<(String, Node)>, DatafusionFsError> {
+        let mut r: Vec<_> = match node {
+            Node::Root => (self.ctx.catalog_names().into_iter())
+                .map(|c| (c.clone(), Node::Catalog(c)))
+                .collect(),
+            Node::Catalog(c) => {
+                let catalog = self.ctx.catalog(c).ok_or(DatafusionFsError::NotFound)?;
+
+                (catalog.schema_names().into_iter())
+                    .map(|s| (s.clone(), Node::Schema(c.clone(), s)))
+                    .collect()
+            }
+            Node::Schema(c, s) => {
+                let schema = self
+                    .ctx
+                    .catalog(c)
+                    .and_then(|catalog| catalog.schema(s))
+                    .ok_or(DatafusionFsError::NotFound)?;
+
+                (schema.table_names().into_iter())
+                    .map(|t| (t.clone(), Node::Table(c.clone(), s.clone(), t)))
+                    .collect()
+            }
+            Node::Table(c, s, t) => {
+                self.table(c, s, t).await?;
+
+                (ExportFormat::ALL.into_iter())
+                    .map(|f| {
+                        (
+                            f.file_name().to_owned(),
+                            Node::File(c.clone(), s.clone(), t.clone(), f),
+                        )
+                    })
+                    .collect()
+            }
+            Node::File(..) => {
+                let ino = self.ino(node.clone());
+                return Err(AsyncFilesystemError::NotADirectory(ino).into());
+            }
+        };
+
+        r.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(r)
+    }
+
+    /// Check that `node` is still registered.
+    async fn check_exists(&self, node: &Node) -> Result<(), DatafusionFsError> {
+        let Some(parent) = node.parent() else {
+            return Ok(());
+        };
+
+        match self.children(&parent).await?.iter().any(|(_, n)| n == node) {
+            true => Ok(()),
+            false => Err(DatafusionFsError::NotFound),
+        }
+    }
+
+    /// The export of the file `ino`, made once per version of its table.
+    async fn spill(
+        &self,
+        ino: u64,
+        catalog: &str,
+        schema: &str,
+        table: &str,
+        format: ExportFormat,
+    ) -> Result<Arc<Spill>, DatafusionFsError> {
+        let table = self.table(catalog, schema, table).await?;
+
+        let slot = {
+            let mut spills = self.spills.lock().unwrap();
+            // Forget the exports of dropped tables, open files keep theirs.
+            spills.retain(|_, slot| slot.table.strong_count() > 0);
+
+            let slot = spills
+                .entry(ino)
+                .or_insert_with(|| Arc::new(SpillSlot::new(&table)));
+            if !slot.is_for(&table) {
+                *slot = Arc::new(SpillSlot::new(&table));
+            }
+
+            slot.clone()
+        };
+
+        let spill = slot
+            .spill
+            .get_or_try_init(|| async { Spill::new(&self.ctx, table, format).await.map(Arc::new) })
+            .await?;
+
+        Ok(spill.clone())
+    }
+
+    /// The attributes of `node`, exporting it if it is a file.
+    async fn attr(&self, ino: u64, node: &Node) -> Result<FileAttr, DatafusionFsError> {
+        let (kind, perm, nlink, size) = match node {
+            Node::File(c, s, t, format) => {
+                let size = self.spill(ino, c, s, t, *format).await?.size;
+                (FileType::RegularFile, self.options.file_perm, 1, size)
+            }
+            _ => (FileType::Directory, self.options.dir_perm, 2, 0),
+        };
+
+        Ok(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(BLOCK_SIZE),
+            atime: self.time,
+            mtime: self.time,
+            ctime: self.time,
+            crtime: self.time,
+            kind,
+            perm: perm & !0o222,
+            nlink,
+            uid: self.options.uid,
+            gid: self.options.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE as u32,
+            flags: 0,
+        })
+    }
+}
+
+enum Encoder {
+    Csv(Box<csv::Writer<BufWriter<File>>>),
+    Ndjson(LineDelimitedWriter<BufWriter<File>>),
+}
+
+impl Encoder {
+    fn write(&mut self, batch: &RecordBatch) -> DatafusionResult<()> {
+        match self {
+            Encoder::Csv(writer) => writer.write(batch)?,
+            Encoder::Ndjson(writer) => writer.write(batch.clone())?,
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> DatafusionResult<()> {
+        let mut out = match self {
+            Encoder::Csv(writer) => writer.into_inner(),
+            Encoder::Ndjson(mut writer) => {
+                writer.finish()?;
+                writer.into_inner()
+            }
+        };
+        out.flush()?;
+
+        Ok(())
+    }
+}
+
+/// The export of a file for a version of its table: the provider registered
+/// under its name, and the changes made to it if it is a [`MutableTable`].
+struct SpillSlot {
+    table: Weak<dyn TableProvider>,
+    version: u64,
+    spill: OnceCell<Arc<Spill>>,
+}
+
+impl SpillSlot {
+    fn new(table: &Arc<dyn TableProvider>) -> Self {
+        Self {
+            table: Arc::downgrade(table),
+            version: mutable_version(table),
+            spill: OnceCell::new(),
+        }
+    }
+
+    fn is_for(&self, table: &Arc<dyn TableProvider>) -> bool {
+        Weak::ptr_eq(&self.table, &Arc::downgrade(table)) && self.version == mutable_version(table)
+    }
+}
+
+fn mutable_version(table: &Arc<dyn TableProvider>) -> u64 {
+    (table.as_any().downcast_ref::<MutableTable>()).map_or(0, MutableTable::version)
+}
+
+/// A file export, written to an anonymous temporary file.
+struct Spill {
+    file: File,
+    size: u64,
+}
+
+impl Spill {
+    async fn new(
+        ctx: &SessionContext,
+        table: Arc<dyn TableProvider>,
+        format: ExportFormat,
+    ) -> DatafusionResult<Self> {
+        let mut file = tempfile::tempfile()?;
+
+        if format == ExportFormat::Schema {
+            write_schema(&mut file, &table.schema())?;
+
+            return Ok(Self {
+                size: file.metadata()?.len(),
+                file,
+            });
+        }
+
+        let mut stream = ctx.read_table(table)?.execute_stream().await?;
+        let out = BufWriter::new(file.try_clone()?);
+
+        match format {
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_max_row_group_size(ROW_GROUP_SIZE)
+                    .build();
+                let mut writer = ArrowWriter::try_new(out, stream.schema(), Some(properties))?;
+
+                while let Some(batch) = stream.next().await {
+                    writer.write(&batch?)?;
+                }
+                writer.close()?;
+            }
+            _ => {
+                let mut encoder = match format {
+                    ExportFormat::Csv => Encoder::Csv(Box::new(csv::Writer::new(out))),
+                    _ => Encoder::Ndjson(LineDelimitedWriter::new(out)),
+                };
+                // Writes the CSV header even if the table is empty.
+                encoder.write(&RecordBatch::new_empty(stream.schema()))?;
+
+                while let Some(batch) = stream.next().await {
+                    encoder.write(&batch?)?;
+                }
+                encoder.finish()?;
+            }
+        }
+
+        Ok(Self {
+            size: file.metadata()?.len(),
+            file,
+        })
+    }
+
+    fn read(&self, offset: u64, size: u32) -> io::Result<Vec<u8>> {
+        let len = self.size.saturating_sub(offset).min(size as u64);
+        let mut r = vec![0; len as usize];
+        self.file.read_exact_at(&mut r, offset)?;
+
+        Ok(r)
+    }
+}
+
+/// The fields of `schema` as JSON, with their types as displayed by Arrow.
+fn write_schema(out: &mut impl Write, schema: &Schema) -> DatafusionResult<()> {
+    let fields: Vec<_> = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            json!({
+                "name": f.name(),
+                "data_type": f.data_type().to_string(),
+                "nullable": f.is_nullable(),
+            })
+        })
+        .collect();
+
+    serde_json::to_writer_pretty(&mut *out, &json!({ "fields": fields }))
+        .map_err(io::Error::from)?;
+    out.write_all(b"\n")?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl AsyncFilesystem for TableExportFs {
+    type Error = DatafusionFsError;
+    type Handle = TableExportHandle;
+
+    async fn getattr(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        debug!("getattr({})", ino);
+
+        let node = self.node(ino)?;
+        self.check_exists(&node).await?;
+
+        Ok((TTL, self.attr(ino, &node).await?))
+    }
+
+    async fn lookup(
+        &self,
+        _req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        debug!("lookup({}, {})", parent, name);
+
+        let node = self.node(parent)?;
+        let (_, child) = self
+            .children(&node)
+            .await?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .ok_or(DatafusionFsError::NotFound)?;
+
+        let ino = self.ino(child.clone());
+
+        Ok((TTL, self.attr(ino, &child).await?, 0))
+    }
+
+    async fn readdir(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        _fh: &TableExportHandle,
+        offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error> {
+        debug!("readdir({}, {})", ino, offset);
+
+        let node = self.node(ino)?;
+        let parent = node.parent().map_or(ino, |p| self.ino(p));
+
+        let dots = [(ino, "."), (parent, "..")]
+            .map(|(ino, name)| (ino, FileType::Directory, name.to_owned()));
+        let children = self
+            .children(&node)
+            .await?
+            .into_iter()
+            .map(|(name, child)| {
+                let kind = match child {
+                    Node::File(..) => FileType::RegularFile,
+                    _ => FileType::Directory,
+                };
+                (self.ino(child), kind, name)
+            });
+
+        let r = dots
+            .into_iter()
+            .chain(children)
+            .enumerate()
+            .skip(offset.max(0) as usize)
+            .map(|(i, (ino, kind, name))| (ino, i as i64 + 1, kind, name))
+            .collect();
+
+        Ok(r)
+    }
+
+    async fn open(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        flags: i32,
+    ) -> Result<(TableExportHandle, u32), Self::Error> {
+        debug!("open({}, {})", ino, flags);
+
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(AsyncFilesystemError::ReadOnly.into());
+        }
+
+        let Node::File(c, s, t, format) = self.node(ino)? else {
+            return Err(AsyncFilesystemError::IsADirectory(ino).into());
+        };
+
+        let handle = TableExportHandle {
+            spill: Some(self.spill(ino, &c, &s, &t, format).await?),
+        };
+
+        // The table can change while the kernel still caches the previous size.
+        Ok((handle, FOPEN_DIRECT_IO))
+    }
+
+    async fn read(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        fh: &TableExportHandle,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+    ) -> Result<Vec<u8>, Self::Error> {
+        debug!("read({}, {}, {})", ino, offset, size);
+
+        let spill = fh
+            .spill
+            .as_ref()
+            .ok_or(AsyncFilesystemError::IsADirectory(ino))?;
+        let data = spill
+            .read(offset.max(0) as u64, size)
+            .map_err(DataFusionError::from)?;
+
+        Ok(data)
+    }
+
+    async fn access(&self, req: &RequestContext, ino: u64, mask: i32) -> Result<(), Self::Error> {
+        debug!("access({}, {:o})", ino, mask);
+
+        let (_, attr) = self.getattr(req, ino).await?;
+
+        if req.can_access(&attr, mask) {
+            Ok(())
+        } else {
+            Err(AsyncFilesystemError::PermissionDenied(ino).into())
+        }
+    }
+}
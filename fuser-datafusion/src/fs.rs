@@ -1,122 +1,922 @@
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use async_trait::async_trait;
-use datafusion::prelude::*;
+use datafusion::{
+    arrow::{
+        array::{Array, StringArray},
+        record_batch::RecordBatch,
+    },
+    error::DataFusionError,
+    prelude::*,
+    scalar::ScalarValue,
+};
 
 use fuser_async::{
-    async_filesystem::AsyncFilesystem,
-    fuser::{FileAttr, FileType},
+    async_filesystem::{AsyncFilesystem, RequestContext, SetAttr, Statfs},
+    errors::AsyncFilesystemError,
+    fuser::{consts::FOPEN_DIRECT_IO, FileAttr, FileType, FUSE_ROOT_ID},
 };
 use itertools::izip;
 use log::debug;
 
 use crate::{
-    conversion::{to_file_attr, BatchesIterators},
+    conform_table,
+    control::{is_query, ControlDir},
+    conversion::{column, first_u64, to_file_attr, BatchesIterators, BLOCK_SIZE, TTL},
     errors::DatafusionFsError,
+    parquet::{write_table, SnapshotOptions},
+    queries::{Prepared, Queries, QueryDir},
+    writable::{Entry, OpenFile, Writable},
+    CHUNKS_SCHEMA, CONTENT_SCHEMA, METADATA_FULL_SCHEMA, METADATA_OPTIONAL_FIELDS, METADATA_SCHEMA,
+    XATTRS_SCHEMA,
 };
 
 pub const METADATA_TABLE: &str = "metadata";
 pub const CONTENT_TABLE: &str = "content";
+/// Optional table of extended attributes, see [`XATTRS_SCHEMA`](crate::XATTRS_SCHEMA).
+pub const XATTRS_TABLE: &str = "xattrs";
+/// Optional chunked content, see [`CHUNKS_SCHEMA`](crate::CHUNKS_SCHEMA).
+///
+/// When it is registered files are read from it, a range at a time, and the
+/// `content` table only needs to provide their sizes.
+pub const CHUNKS_TABLE: &str = "chunks";
+
+/// Inode of the first query directory, see [`DatafusionFs::with_query_dir`].
+///
+/// Inodes from there on are reserved, and must not be used by the metadata table.
+pub const QUERY_DIR_INO: u64 = 1 << 63;
+/// Inode of the control directory, see [`DatafusionFs::with_control_dir`]. Its
+/// files get the inodes that follow.
+pub const CONTROL_DIR_INO: u64 = QUERY_DIR_INO + (1 << 62);
+/// Offset of the control and query directories in the root listing, after the
+/// root entries of the metadata table.
+const VIRTUAL_DIR_OFFSET: i64 = 1 << 62;
+
+/// Extended attribute exposing the `id` column of the metadata table.
+const ID_XATTR: &str = "user.id";
+
+/// Attributes used for rows of the metadata table that don't carry them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatafusionFsOptions {
+    pub uid: u32,
+    pub gid: u32,
+    pub file_perm: u16,
+    pub dir_perm: u16,
+    /// Size of every chunk of a file in the chunks table but the last one.
+    pub chunk_size: u64,
+    /// Size up to which files of a writable filesystem or of the control
+    /// directory, kept in memory while open, can be written or truncated. Larger
+    /// sizes fail with `EFBIG`.
+    pub max_file_size: u64,
+    /// Time to live of attributes and entries. The kernel can't be told about
+    /// changes made to the tables behind the mount's back, so it serves stale
+    /// attributes and entries for up to this long: shorten it when the tables
+    /// change under a mounted filesystem.
+    pub ttl: Duration,
+    /// Time to live of the entries of query directories, which change with the
+    /// tables, unlike other entries.
+    pub query_dir_ttl: Duration,
+}
+
+impl Default for DatafusionFsOptions {
+    fn default() -> Self {
+        Self {
+            uid: 501,
+            gid: 20,
+            file_perm: 0o644,
+            dir_perm: 0o755,
+            chunk_size: 128 * 1024,
+            max_file_size: 1 << 30,
+            ttl: TTL,
+            query_dir_ttl: Duration::from_secs(1),
+        }
+    }
+}
 
 pub struct DatafusionFs {
     ctx: SessionContext,
+    options: DatafusionFsOptions,
+    queries: Queries,
+    writable: Option<Writable>,
+    query_dirs: Vec<QueryDir>,
+    control: Option<ControlDir>,
+}
+
+/// Per-open state: the file content, queried once when the file is opened,
+/// unless it is read from the chunks table.
+///
+/// Files open for writing in a writable filesystem share their content instead,
+/// until their last handle is released.
+#[derive(Default)]
+pub struct DatafusionHandle {
+    content: Option<Vec<u8>>,
+    file: Option<Arc<OpenFile>>,
 }
 
 impl DatafusionFs {
+    /// Create a filesystem from the tables registered in `ctx`, without checking
+    /// them. Rows whose columns don't have the expected types are skipped.
     pub fn new(ctx: SessionContext) -> Self {
-        Self { ctx }
+        Self {
+            ctx,
+            options: DatafusionFsOptions::default(),
+            queries: Queries::new(),
+            writable: None,
+            query_dirs: vec![],
+            control: None,
+        }
+    }
+
+    /// Like [`new`](Self::new), checking the schemas of the registered tables first
+    /// and casting their columns where it is safe, see [`conform_table`].
+    pub async fn try_new(ctx: SessionContext) -> Result<Self, DatafusionFsError> {
+        conform_table(
+            &ctx,
+            METADATA_TABLE,
+            &METADATA_SCHEMA,
+            &METADATA_OPTIONAL_FIELDS,
+        )
+        .await?;
+        conform_table(&ctx, CONTENT_TABLE, &CONTENT_SCHEMA, &[]).await?;
+
+        for (table, schema) in [
+            (CHUNKS_TABLE, &*CHUNKS_SCHEMA),
+            (XATTRS_TABLE, &*XATTRS_SCHEMA),
+        ] {
+            if ctx.table_exist(table)? {
+                conform_table(&ctx, table, schema, &[]).await?;
+            }
+        }
+
+        Ok(Self::new(ctx))
+    }
+
+    /// Like [`try_new`](Self::try_new), with the metadata and content tables copied
+    /// to memory so that files can be created, written, renamed and removed.
+    ///
+    /// Changes are kept in memory until [`flush_to_parquet`](Self::flush_to_parquet).
+    /// The chunks table is not supported.
+    ///
+    /// Changes are checked against the mode, owner and group of the files and of
+    /// their directories like the kernel would, so mounting with
+    /// `DefaultPermissions` is not needed.
+    pub async fn try_new_writable(ctx: SessionContext) -> Result<Self, DatafusionFsError> {
+        let mut fs = Self::try_new(ctx).await?;
+
+        if fs.chunked()? {
+            return Err(DatafusionFsError::NotImplemented);
+        }
+
+        fs.writable = Some(Writable::new(&fs.ctx).await?);
+
+        Ok(fs)
+    }
+
+    /// Write the metadata and content tables under `dest`, in the layout of
+    /// [`snapshot`](crate::parquet::snapshot), replacing a previous flush.
+    ///
+    /// Files still open for writing are written as of their last release.
+    pub async fn flush_to_parquet(
+        &self,
+        dest: impl AsRef<Path>,
+        options: &SnapshotOptions,
+    ) -> Result<(), DatafusionFsError> {
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        let dest = dest.as_ref();
+        write_table(
+            &dest.join(METADATA_TABLE),
+            METADATA_FULL_SCHEMA.clone(),
+            &writable.metadata.batches(),
+            options,
+        )?;
+        write_table(
+            &dest.join(CONTENT_TABLE),
+            CONTENT_SCHEMA.clone(),
+            &writable.content.batches(),
+            options,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn with_options(mut self, options: DatafusionFsOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Add a read-only directory `name` to the root, listing the rows of `sql`.
+    ///
+    /// The query returns the `ino`, `name` and `type` of its entries, like the
+    /// metadata table, and they are the files of those inodes. It should order its
+    /// rows, since directories are listed a page at a time. Its entries are cached
+    /// by the kernel for [`DatafusionFsOptions::query_dir_ttl`].
+    ///
+    /// ```no_run
+    /// # fn f(fs: fuser_datafusion::DatafusionFs) -> fuser_datafusion::DatafusionFs {
+    /// fs.with_query_dir(
+    ///     "recent",
+    ///     "SELECT ino, name, type FROM metadata
+    ///     WHERE type = 'RegularFile' ORDER BY mtime DESC LIMIT 100",
+    /// )
+    /// # }
+    /// ```
+    pub fn with_query_dir(mut self, name: impl Into<String>, sql: impl Into<String>) -> Self {
+        self.query_dirs.push(QueryDir::new(name.into(), sql.into()));
+        self
+    }
+
+    /// Add a directory `name` to the root, where queries can be run by writing
+    /// them to files.
+    ///
+    /// Once `foo.sql` is written and closed its query runs against the tables of
+    /// the filesystem, and `foo.csv`, `foo.json` and `foo.parquet` hold the result,
+    /// or `foo.err` the error. Files can be removed, but only `.sql` files can be
+    /// created. They are kept in memory, also when the filesystem is read-only.
+    ///
+    /// Like `/tmp`, the directory is writable by everyone and sticky: files belong
+    /// to the user who created the query, and only they can remove them.
+    pub fn with_control_dir(mut self, name: impl Into<String>) -> Self {
+        self.control = Some(ControlDir::new(name.into()));
+        self
+    }
+
+    fn writable(&self) -> Result<&Writable, DatafusionFsError> {
+        self.writable
+            .as_ref()
+            .ok_or_else(|| AsyncFilesystemError::ReadOnly.into())
+    }
+
+    /// `attr` with what the tables don't know about: the size of a file open for
+    /// writing, and the control and query directories linking to the root.
+    fn complete_attr(&self, mut attr: FileAttr) -> FileAttr {
+        if let Some(file) = self.writable.as_ref().and_then(|w| w.file(attr.ino)) {
+            attr.size = file.len();
+            attr.blocks = attr.size.div_ceil(BLOCK_SIZE);
+        }
+
+        if attr.ino == FUSE_ROOT_ID {
+            attr.nlink += (self.query_dirs.len() + self.control.iter().len()) as u32;
+        }
+
+        attr
+    }
+
+    fn query_dir(&self, ino: u64) -> Option<(usize, &QueryDir)> {
+        let index = ino.checked_sub(QUERY_DIR_INO)? as usize;
+
+        self.query_dirs.get(index).map(|dir| (index, dir))
+    }
+
+    fn is_control_dir(&self, ino: u64) -> bool {
+        ino == CONTROL_DIR_INO && self.control.is_some()
+    }
+
+    /// Whether `ino` is in the range of the control files, which may have been
+    /// removed since.
+    fn is_control_file(&self, ino: u64) -> bool {
+        ino > CONTROL_DIR_INO && self.control.is_some()
+    }
+
+    /// Whether `ino` isn't in the tables: a query or control directory, or a
+    /// control file.
+    fn is_virtual(&self, ino: u64) -> bool {
+        self.query_dir(ino).is_some() || self.is_control_dir(ino) || self.is_control_file(ino)
+    }
+
+    /// Fail for query and control directories, which can't be changed.
+    fn check_changeable(&self, ino: u64) -> Result<(), DatafusionFsError> {
+        match self.query_dir(ino).is_some() || self.is_control_dir(ino) {
+            true => Err(AsyncFilesystemError::PermissionDenied(ino).into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Attributes of a query or control directory: those of the root, without
+    /// write permissions for query directories, and writable by everyone and
+    /// sticky for the control directory.
+    async fn virtual_dir_attr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), DatafusionFsError> {
+        let (ttl, mut attr) = self.getattr(req, FUSE_ROOT_ID).await?;
+
+        attr.ino = ino;
+        attr.nlink = 2;
+        attr.perm = match ino {
+            CONTROL_DIR_INO => 0o777 | libc::S_ISVTX as u16,
+            _ => attr.perm & !0o222,
+        };
+
+        Ok((ttl, attr))
+    }
+
+    /// The control directory and the query directories, listed in the root after
+    /// `offset`, as `(ino, offset, name)`.
+    fn root_virtual_dirs(&self, offset: i64) -> Vec<(u64, i64, String)> {
+        let skip = (offset - VIRTUAL_DIR_OFFSET).max(0) as usize;
+
+        let control = self.control.iter().map(|c| (CONTROL_DIR_INO, &c.name));
+        let queries = (self.query_dirs.iter().enumerate())
+            .map(|(i, dir)| (QUERY_DIR_INO + i as u64, &dir.name));
+
+        control
+            .chain(queries)
+            .enumerate()
+            .skip(skip)
+            .map(|(i, (ino, name))| (ino, VIRTUAL_DIR_OFFSET + i as i64 + 1, name.clone()))
+            .collect()
+    }
+
+    /// Attributes of a control file. Results can't be written, and change when
+    /// queries run, so they are not cached.
+    fn control_file_attr(&self, ino: u64) -> Result<(Duration, FileAttr), DatafusionFsError> {
+        let (name, file) = self
+            .control
+            .as_ref()
+            .and_then(|c| c.file(ino))
+            .ok_or(DatafusionFsError::NotFound)?;
+
+        let size = file.content.len();
+        let perm = match is_query(&name) {
+            true => self.options.file_perm,
+            false => self.options.file_perm & !0o222,
+        };
+
+        let attr = FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(BLOCK_SIZE),
+            atime: file.mtime,
+            mtime: file.mtime,
+            ctime: file.mtime,
+            crtime: file.mtime,
+            kind: FileType::RegularFile,
+            perm,
+            nlink: 1,
+            uid: file.uid,
+            gid: file.gid,
+            rdev: 0,
+            blksize: BLOCK_SIZE as u32,
+            flags: 0,
+        };
+
+        Ok((Duration::ZERO, attr))
+    }
+
+    /// Entries of the control directory after `offset`: `.`, `..` and its files.
+    fn control_dir_entries(
+        &self,
+        control: &ControlDir,
+        offset: i64,
+    ) -> Vec<(u64, i64, FileType, String)> {
+        let dots = [(CONTROL_DIR_INO, "."), (FUSE_ROOT_ID, "..")]
+            .map(|(ino, name)| (ino, FileType::Directory, name.to_owned()));
+        let files =
+            (control.entries().into_iter()).map(|(ino, name)| (ino, FileType::RegularFile, name));
+
+        dots.into_iter()
+            .chain(files)
+            .enumerate()
+            .skip(offset.max(0) as usize)
+            .map(|(i, (ino, kind, name))| (ino, i as i64 + 1, kind, name))
+            .collect()
+    }
+
+    /// Entries of a query directory after `offset`: `.`, `..` and the rows of its query.
+    async fn query_dir_entries(
+        &self,
+        index: usize,
+        dir: &QueryDir,
+        offset: i64,
+    ) -> Result<Vec<(u64, i64, FileType, String)>, DatafusionFsError> {
+        let dots = [(QUERY_DIR_INO + index as u64, "."), (FUSE_ROOT_ID, "..")];
+
+        let mut r: Vec<_> = dots
+            .iter()
+            .enumerate()
+            .skip(offset.max(0) as usize)
+            .map(|(i, (ino, name))| (*ino, i as i64 + 1, FileType::Directory, name.to_string()))
+            .collect();
+
+        let skip = (offset - dots.len() as i64).max(0);
+        let batches = dir
+            .entries(&self.ctx)
+            .await?
+            .limit(skip as usize, None)?
+            .collect()
+            .await?;
+
+        let rows = izip!(batches.inos(0), batches.kinds(2), batches.names(1))
+            .enumerate()
+            .filter_map(|(i, (ino, kind, name))| match (ino, kind, name) {
+                (Some(ino), Some(kind), Some(name)) => Some((
+                    ino,
+                    dots.len() as i64 + skip + i as i64 + 1,
+                    kind,
+                    name.to_owned(),
+                )),
+                _ => None,
+            });
+        r.extend(rows);
+
+        Ok(r)
+    }
+
+    fn check_size(&self, ino: u64, size: u64) -> Result<(), DatafusionFsError> {
+        match size > self.options.max_file_size {
+            true => Err(AsyncFilesystemError::FileTooLarge(ino).into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Check that `name` can be added to `parent` by the caller.
+    async fn check_new_entry(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), DatafusionFsError> {
+        self.check_changeable(parent)?;
+
+        let (_, attr) = self.getattr(req, parent).await?;
+
+        if attr.kind != FileType::Directory {
+            return Err(AsyncFilesystemError::NotADirectory(parent).into());
+        }
+        check_access(req, &attr, libc::W_OK | libc::X_OK)?;
+
+        match self.lookup(req, parent, name).await {
+            Ok(_) => Err(AsyncFilesystemError::AlreadyExists(parent, name.to_owned()).into()),
+            Err(DatafusionFsError::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check that the caller can remove the entry `attr` from `parent`: it needs
+    /// write access to `parent`, and to own one of them if `parent` is sticky.
+    async fn check_removable(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        attr: &FileAttr,
+    ) -> Result<(), DatafusionFsError> {
+        let (_, dir) = self.getattr(req, parent).await?;
+        check_access(req, &dir, libc::W_OK | libc::X_OK)?;
+
+        let sticky = dir.perm & libc::S_ISVTX as u16 != 0;
+        if sticky && req.uid != 0 && req.uid != dir.uid && req.uid != attr.uid {
+            return Err(AsyncFilesystemError::NotPermitted(attr.ino).into());
+        }
+
+        Ok(())
+    }
+
+    /// Check that `dir` is not `ino` or one of its descendants, following `..`.
+    async fn check_not_within(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        mut dir: u64,
+    ) -> Result<(), DatafusionFsError> {
+        loop {
+            if dir == ino {
+                return Err(AsyncFilesystemError::MoveIntoSubtree(ino).into());
+            }
+
+            match self.lookup(req, dir, "..").await {
+                Ok((_, parent, _)) if parent.ino != dir => dir = parent.ino,
+                Ok(_) | Err(DatafusionFsError::NotFound) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn query(
+        &self,
+        query: &Prepared,
+        params: Vec<ScalarValue>,
+    ) -> Result<Vec<RecordBatch>, DatafusionFsError> {
+        Ok(query.execute(&self.ctx, params).await?.collect().await?)
+    }
+
+    async fn content(&self, ino: u64) -> Result<Vec<u8>, DatafusionFsError> {
+        self.query(&self.queries.content, vec![ino.into()])
+            .await?
+            .content(1)
+            .flatten()
+            .next()
+            .map(|c| c.to_vec())
+            .ok_or(DatafusionFsError::NotFound)
+    }
+
+    fn chunked(&self) -> Result<bool, DatafusionFsError> {
+        Ok(self.ctx.table_exist(CHUNKS_TABLE)?)
+    }
+
+    /// Read `size` bytes at `offset` from the chunks covering that range.
+    ///
+    /// A missing chunk or one shorter than `chunk_size` is the end of the file.
+    async fn read_chunks(
+        &self,
+        ino: u64,
+        offset: u64,
+        size: u32,
+    ) -> Result<Vec<u8>, DatafusionFsError> {
+        let chunk_size = self.options.chunk_size;
+
+        if size == 0 || chunk_size == 0 {
+            return Ok(vec![]);
+        }
+
+        let first = offset / chunk_size;
+        let last = (offset + size as u64 - 1) / chunk_size;
+
+        let batches = self
+            .query(
+                &self.queries.chunks,
+                vec![ino.into(), first.into(), last.into()],
+            )
+            .await?;
+
+        let mut data = Vec::with_capacity(size as usize);
+        let mut skip = (offset - first * chunk_size) as usize;
+
+        for (expected, (index, chunk)) in (first..).zip(izip!(batches.inos(0), batches.content(1)))
+        {
+            let (Some(index), Some(chunk)) = (index, chunk) else {
+                break;
+            };
+
+            if index != expected {
+                break;
+            }
+
+            let start = skip.min(chunk.len());
+            let end = start
+                .saturating_add(size as usize - data.len())
+                .min(chunk.len());
+            data.extend_from_slice(&chunk[start..end]);
+            skip = 0;
+
+            if (chunk.len() as u64) < chunk_size {
+                break;
+            }
+        }
+
+        Ok(data)
+    }
+
+    async fn target(&self, ino: u64) -> Result<String, DatafusionFsError> {
+        let batches = self.query(&self.queries.target, vec![ino.into()]).await?;
+
+        let Some(batch) = batches.iter().find(|b| b.num_rows() > 0) else {
+            return Err(DatafusionFsError::NotFound);
+        };
+
+        match column::<StringArray>(batch, "type").map(|kinds| kinds.value(0)) {
+            Some("Symlink") => {}
+            _ => return Err(AsyncFilesystemError::NotASymlink(ino).into()),
+        }
+
+        column::<StringArray>(batch, "target")
+            .filter(|targets| targets.is_valid(0))
+            .map(|targets| targets.value(0).to_owned())
+            .ok_or_else(|| AsyncFilesystemError::NotASymlink(ino).into())
+    }
+
+    async fn id(&self, ino: u64) -> Result<String, DatafusionFsError> {
+        let batches = self.query(&self.queries.id, vec![ino.into()]).await?;
+
+        let id = batches.names(0).flatten().next().map(str::to_owned);
+
+        id.ok_or(DatafusionFsError::NotFound)
+    }
+
+    /// Rows of the xattrs table for `ino`, optionally restricted to one name.
+    async fn xattrs(
+        &self,
+        ino: u64,
+        name: Option<&str>,
+    ) -> Result<Vec<RecordBatch>, DatafusionFsError> {
+        if !self.ctx.table_exist(XATTRS_TABLE)? {
+            return Ok(vec![]);
+        }
+
+        let batches = match name {
+            Some(name) => {
+                self.query(&self.queries.xattr, vec![ino.into(), name.into()])
+                    .await?
+            }
+            None => self.query(&self.queries.xattrs, vec![ino.into()]).await?,
+        };
+
+        Ok(batches)
     }
 }
 
 #[async_trait]
 impl AsyncFilesystem for DatafusionFs {
     type Error = DatafusionFsError;
-    async fn getattr(&self, ino: u64) -> Result<(Duration, FileAttr), Self::Error> {
+    type Handle = DatafusionHandle;
+
+    async fn getattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
         debug!("getattr({})", ino);
 
-        let query = format!(
-            r#"SELECT 
-            metadata.ino, 
-            type,
-            size
-            FROM metadata 
-            LEFT JOIN content ON metadata.ino = content.ino 
-            WHERE metadata.ino = {} LIMIT 1"#,
-            ino,
-        );
+        if self.query_dir(ino).is_some() || self.is_control_dir(ino) {
+            return self.virtual_dir_attr(req, ino).await;
+        }
+
+        if self.is_control_file(ino) {
+            return self.control_file_attr(ino);
+        }
+
+        let batches = self.query(&self.queries.getattr, vec![ino.into()]).await?;
+        let (ttl, attr) = to_file_attr(batches, &self.options)?;
+
+        Ok((ttl, self.complete_attr(attr)))
+    }
 
-        let batches = self.ctx.sql(&query).await?.collect().await?;
+    async fn setattr(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        fh: Option<&DatafusionHandle>,
+        attr: SetAttr,
+    ) -> Result<(Duration, FileAttr), Self::Error> {
+        debug!("setattr({}, {:?})", ino, attr);
+
+        if let (Some(control), true) = (&self.control, self.is_control_file(ino)) {
+            let (name, file) = control.file(ino).ok_or(DatafusionFsError::NotFound)?;
+            let (_, current) = self.control_file_attr(ino)?;
+
+            // Only the size of query files and the times can change.
+            let chmod = attr
+                .mode
+                .is_some_and(|mode| mode as u16 & 0o7777 != current.perm);
+            let chown = attr.uid.is_some_and(|uid| uid != current.uid)
+                || attr.gid.is_some_and(|gid| gid != current.gid);
+            if chmod || chown {
+                return Err(AsyncFilesystemError::NotPermitted(ino).into());
+            }
+            check_setattr(req, &current, &attr)?;
+
+            if let Some(size) = attr.size {
+                if !is_query(&name) {
+                    return Err(AsyncFilesystemError::PermissionDenied(ino).into());
+                }
+                check_access(req, &current, libc::W_OK)?;
+                self.check_size(ino, size)?;
 
-        let r = to_file_attr(batches);
+                file.content.truncate(size);
+                control.touch(ino, SystemTime::now());
+            }
+            if let Some(mtime) = attr.mtime {
+                control.touch(ino, mtime);
+            }
 
-        r
+            return self.control_file_attr(ino);
+        }
+
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        self.check_changeable(ino)?;
+        let (_, current) = self.getattr(req, ino).await?;
+        check_setattr(req, &current, &attr)?;
+        let now = SystemTime::now();
+
+        if let Some(size) = attr.size {
+            if current.kind == FileType::Directory {
+                return Err(AsyncFilesystemError::IsADirectory(ino).into());
+            }
+            self.check_size(ino, size)?;
+
+            // Truncating through a handle was checked when opening it.
+            let handle = fh.and_then(|fh| fh.file.clone());
+            if handle.is_none() {
+                check_access(req, &current, libc::W_OK)?;
+            }
+
+            match handle.or_else(|| writable.file(ino)) {
+                // Committed when the file is released.
+                Some(file) => file.truncate(size),
+                None => {
+                    let mut content = self.content(ino).await?;
+                    content.resize(size as usize, 0);
+                    writable.commit(ino, &content, now)?;
+                }
+            }
+        }
+
+        writable.set_attr(ino, &attr, now)?;
+
+        self.getattr(req, ino).await
     }
 
     async fn lookup(
         &self,
+        req: &RequestContext,
         parent: u64,
         name: &str,
     ) -> Result<(Duration, FileAttr, u64), Self::Error> {
         debug!("lookup({}, {})", parent, name);
 
-        let query = format!(
-            r#"
-            SELECT
-            metadata.ino, 
-            type,
-            size
-            FROM metadata 
-            LEFT JOIN content ON metadata.ino = content.ino 
-            WHERE 
-            name = '{}' 
-            and parent_ino = {} LIMIT 1"#,
-            name, parent
-        );
+        if parent == FUSE_ROOT_ID {
+            let dir = self
+                .root_virtual_dirs(0)
+                .into_iter()
+                .find(|(_, _, n)| n == name);
+
+            if let Some((ino, _, _)) = dir {
+                let (ttl, attr) = self.virtual_dir_attr(req, ino).await?;
+                return Ok((ttl, attr, 0));
+            }
+        }
+
+        if let (Some(control), true) = (&self.control, self.is_control_dir(parent)) {
+            let ino = control.lookup(name).ok_or(DatafusionFsError::NotFound)?;
+            let (ttl, attr) = self.control_file_attr(ino)?;
+
+            return Ok((ttl, attr, 0));
+        }
 
-        let batches = self.ctx.sql(&query).await?.collect().await?;
+        if let Some((_, dir)) = self.query_dir(parent) {
+            let batches = dir
+                .entries(&self.ctx)
+                .await?
+                .filter(col("name").eq(lit(name)))?
+                .limit(0, Some(1))?
+                .collect()
+                .await?;
 
-        let (ttl, attr) = to_file_attr(batches)?;
+            let ino = batches
+                .inos(0)
+                .flatten()
+                .next()
+                .ok_or(DatafusionFsError::NotFound)?;
+            let (ttl, attr) = self.getattr(req, ino).await?;
+
+            return Ok((ttl.min(self.options.query_dir_ttl), attr, 0));
+        }
+
+        let batches = self
+            .query(&self.queries.lookup, vec![parent.into(), name.into()])
+            .await?;
+
+        let ino = batches
+            .inos(0)
+            .flatten()
+            .next()
+            .ok_or(DatafusionFsError::NotFound)?;
+        let (ttl, attr) = self.getattr(req, ino).await?;
 
         Ok((ttl, attr, 0))
     }
 
     async fn readdir(
         &self,
+        _req: &RequestContext,
         ino: u64,
-        _fh: u64,
+        _fh: &DatafusionHandle,
         offset: i64,
     ) -> Result<Vec<(u64, i64, FileType, String)>, Self::Error> {
         debug!("readdir({}, {})", ino, offset);
 
-        let batches = self
-            .ctx
-            .table(METADATA_TABLE)
-            .await?
-            .filter(col("parent_ino").eq(lit(ino)))?
-            .select_columns(&["ino", "name", "type"])?
-            .sort(vec![col("ino").sort(true, true)])?
-            .limit(offset as usize, None)?
-            .collect()
-            .await?;
+        if let Some((index, dir)) = self.query_dir(ino) {
+            return self.query_dir_entries(index, dir, offset).await;
+        }
 
-        let r = izip!(batches.inos(0), batches.kinds(2), batches.names(1))
-            .enumerate()
-            .map(|(i, (ino, kind, name))| match (ino, kind, name) {
-                (Some(ino), Some(kind), Some(name)) => {
-                    Some((ino, i as i64 + 1, kind, name.to_owned()))
-                }
-                _ => None,
-            })
-            .flatten()
-            .collect();
+        if let (Some(control), true) = (&self.control, self.is_control_dir(ino)) {
+            return Ok(self.control_dir_entries(control, offset));
+        }
+
+        let mut r = vec![];
+
+        if offset < VIRTUAL_DIR_OFFSET {
+            let batches = self
+                .queries
+                .readdir
+                .execute(&self.ctx, vec![ino.into()])
+                .await?
+                .limit(offset as usize, None)?
+                .collect()
+                .await?;
+
+            let entries = izip!(batches.inos(0), batches.kinds(2), batches.names(1))
+                .enumerate()
+                .filter_map(|(i, (ino, kind, name))| match (ino, kind, name) {
+                    (Some(ino), Some(kind), Some(name)) => {
+                        Some((ino, offset + i as i64 + 1, kind, name.to_owned()))
+                    }
+                    _ => None,
+                });
+            r.extend(entries);
+        }
+
+        if ino == FUSE_ROOT_ID {
+            let dirs = self.root_virtual_dirs(offset).into_iter();
+            r.extend(dirs.map(|(ino, o, name)| (ino, o, FileType::Directory, name)));
+        }
+
+        Ok(r)
+    }
+
+    async fn readdirplus(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        _fh: &DatafusionHandle,
+        offset: i64,
+    ) -> Result<Vec<(i64, String, Duration, FileAttr, u64)>, Self::Error> {
+        debug!("readdirplus({}, {})", ino, offset);
+
+        let entries = match (self.query_dir(ino), &self.control) {
+            (Some((index, dir)), _) => Some(self.query_dir_entries(index, dir, offset).await?),
+            (None, Some(control)) if self.is_control_dir(ino) => {
+                Some(self.control_dir_entries(control, offset))
+            }
+            _ => None,
+        };
+
+        if let Some(entries) = entries {
+            let mut r = vec![];
+
+            let max_ttl = match self.query_dir(ino) {
+                Some(_) => self.options.query_dir_ttl,
+                None => Duration::MAX,
+            };
+
+            for (ino, offset, _, name) in entries {
+                let (ttl, attr) = self.getattr(req, ino).await?;
+                r.push((offset, name, ttl.min(max_ttl), attr, 0));
+            }
+
+            return Ok(r);
+        }
+
+        let mut r = vec![];
+
+        if offset < VIRTUAL_DIR_OFFSET {
+            let batches = self
+                .queries
+                .readdirplus
+                .execute(&self.ctx, vec![ino.into()])
+                .await?
+                .limit(offset as usize, None)?
+                .collect()
+                .await?;
+
+            // `m.*` is in the column order of the registered table.
+            let name = match batches.first() {
+                Some(batch) => batch
+                    .schema()
+                    .index_of("name")
+                    .map_err(DataFusionError::from)?,
+                None => 0,
+            };
+
+            let entries = izip!(batches.file_attrs(&self.options), batches.names(name))
+                .enumerate()
+                .filter_map(|(i, (attr, name))| match (attr, name) {
+                    (Some(attr), Some(name)) => Some((
+                        offset + i as i64 + 1,
+                        name.to_owned(),
+                        self.options.ttl,
+                        self.complete_attr(attr),
+                        0,
+                    )),
+                    _ => None,
+                });
+            r.extend(entries);
+        }
+
+        if ino == FUSE_ROOT_ID {
+            for (ino, o, name) in self.root_virtual_dirs(offset) {
+                let (ttl, attr) = self.virtual_dir_attr(req, ino).await?;
+                r.push((o, name, ttl, attr, 0));
+            }
+        }
 
         Ok(r)
     }
 
     async fn read(
         &self,
+        _req: &RequestContext,
         ino: u64,
-        _fh: u64,
+        fh: &DatafusionHandle,
         offset: i64,
         size: u32,
         flags: i32,
@@ -127,20 +927,535 @@ impl AsyncFilesystem for DatafusionFs {
             ino, offset, size, flags, lock
         );
 
-        let query = format!(
-            "SELECT size, content FROM {} WHERE ino = {} LIMIT 1",
-            CONTENT_TABLE, ino
+        let offset = offset.max(0) as u64;
+
+        if let Some(file) = &fh.file {
+            return Ok(file.read(offset, size));
+        }
+
+        let content = match &fh.content {
+            Some(content) => Cow::Borrowed(content),
+            None if self.chunked()? => return self.read_chunks(ino, offset, size).await,
+            None => Cow::Owned(self.content(ino).await?),
+        };
+
+        let start = offset.min(content.len() as u64) as usize;
+        let end = start.saturating_add(size as usize).min(content.len());
+
+        Ok(content[start..end].to_vec())
+    }
+
+    async fn open(
+        &self,
+        req: &RequestContext,
+        ino: u64,
+        flags: i32,
+    ) -> Result<(DatafusionHandle, u32), Self::Error> {
+        debug!("open({}, {})", ino, flags);
+
+        let mask = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => libc::R_OK,
+            libc::O_WRONLY => libc::W_OK,
+            _ => libc::R_OK | libc::W_OK,
+        };
+
+        if let (Some(control), true) = (&self.control, self.is_control_file(ino)) {
+            let (_, attr) = self.control_file_attr(ino)?;
+            check_access(req, &attr, mask)?;
+
+            let handle = DatafusionHandle {
+                content: None,
+                file: control.file(ino).map(|(_, file)| file.content),
+            };
+
+            // Results change behind the page cache when queries run.
+            return Ok((handle, FOPEN_DIRECT_IO));
+        }
+
+        let (_, attr) = self.getattr(req, ino).await?;
+        check_access(req, &attr, mask)?;
+
+        if let Some(writable) = &self.writable {
+            let file = match writable.reopen(ino) {
+                Some(file) => Some(file),
+                None if flags & libc::O_ACCMODE != libc::O_RDONLY => {
+                    Some(writable.open(ino, self.content(ino).await?))
+                }
+                None => None,
+            };
+
+            if file.is_some() {
+                return Ok((
+                    DatafusionHandle {
+                        content: None,
+                        file,
+                    },
+                    0,
+                ));
+            }
+        }
+
+        let content = match self.chunked()? {
+            true => None,
+            false => Some(self.content(ino).await?),
+        };
+
+        Ok((
+            DatafusionHandle {
+                content,
+                file: None,
+            },
+            0,
+        ))
+    }
+
+    async fn flush(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        _fh: &DatafusionHandle,
+        _lock_owner: u64,
+    ) -> Result<(), Self::Error> {
+        debug!("flush({})", ino);
+
+        if let (Some(control), true) = (&self.control, self.is_control_file(ino)) {
+            control.run(&self.ctx, ino).await;
+        }
+
+        Ok(())
+    }
+
+    async fn release(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        fh: &DatafusionHandle,
+        _flags: i32,
+        _lock: Option<u64>,
+        _flush: bool,
+    ) -> Result<(), Self::Error> {
+        debug!("release({})", ino);
+
+        // In case the file wasn't flushed.
+        if let (Some(control), true) = (&self.control, self.is_control_file(ino)) {
+            control.run(&self.ctx, ino).await;
+            return Ok(());
+        }
+
+        if let (Some(writable), Some(file)) = (&self.writable, &fh.file) {
+            writable.release(ino, file).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        fh: &DatafusionHandle,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+    ) -> Result<u32, Self::Error> {
+        debug!("write({}, {}, {})", ino, offset, data.len());
+
+        let file = fh
+            .file
+            .as_ref()
+            .ok_or(AsyncFilesystemError::NotWritable(ino))?;
+
+        let offset = offset.max(0) as u64;
+        let end = offset.checked_add(data.len() as u64);
+        self.check_size(ino, end.unwrap_or(u64::MAX))?;
+        file.write(offset, data);
+
+        if let (Some(control), true) = (&self.control, self.is_control_file(ino)) {
+            control.touch(ino, SystemTime::now());
+        }
+
+        Ok(data.len() as u32)
+    }
+
+    async fn create(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+        _flags: i32,
+    ) -> Result<(Duration, FileAttr, u64, DatafusionHandle, u32), Self::Error> {
+        debug!("create({}, {}, {:o})", parent, name, mode);
+
+        if let (Some(control), true) = (&self.control, self.is_control_dir(parent)) {
+            let (_, dir) = self.getattr(req, parent).await?;
+            check_access(req, &dir, libc::W_OK | libc::X_OK)?;
+
+            let file = control.create(name, req.uid, req.gid)?;
+            let (ttl, attr) = self.control_file_attr(file.ino)?;
+            let handle = DatafusionHandle {
+                content: None,
+                file: Some(file.content),
+            };
+
+            return Ok((ttl, attr, 0, handle, FOPEN_DIRECT_IO));
+        }
+
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        self.check_new_entry(req, parent, name).await?;
+
+        let ino = writable.allocate();
+        let now = SystemTime::now();
+        let entry = Entry {
+            ino,
+            kind: FileType::RegularFile,
+            name,
+            parent,
+            mode: mode & !umask,
+            uid: req.uid,
+            gid: req.gid,
+        };
+        writable.insert(&entry, now)?;
+        writable.touch(parent, now)?;
+
+        let (ttl, attr) = self.getattr(req, ino).await?;
+        let handle = DatafusionHandle {
+            content: None,
+            file: Some(writable.open(ino, vec![])),
+        };
+
+        Ok((ttl, attr, 0, handle, 0))
+    }
+
+    async fn mkdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        mode: u32,
+        umask: u32,
+    ) -> Result<(Duration, FileAttr, u64), Self::Error> {
+        debug!("mkdir({}, {}, {:o})", parent, name, mode);
+
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        self.check_new_entry(req, parent, name).await?;
+
+        let ino = writable.allocate();
+        let now = SystemTime::now();
+        let entry = Entry {
+            ino,
+            kind: FileType::Directory,
+            name,
+            parent,
+            mode: mode & !umask,
+            uid: req.uid,
+            gid: req.gid,
+        };
+        writable.insert(&entry, now)?;
+        writable.insert(
+            &Entry {
+                name: ".",
+                parent: ino,
+                ..entry
+            },
+            now,
+        )?;
+        writable.link_parent(ino, parent)?;
+        writable.touch(parent, now)?;
+
+        self.lookup(req, parent, name).await
+    }
+
+    async fn unlink(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        debug!("unlink({}, {})", parent, name);
+
+        if let (Some(control), true) = (&self.control, self.is_control_dir(parent)) {
+            let ino = control.lookup(name).ok_or(DatafusionFsError::NotFound)?;
+            let (_, attr) = self.control_file_attr(ino)?;
+            self.check_removable(req, parent, &attr).await?;
+
+            return match control.remove(name) {
+                true => Ok(()),
+                false => Err(DatafusionFsError::NotFound),
+            };
+        }
+
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        self.check_changeable(parent)?;
+        let (_, attr, _) = self.lookup(req, parent, name).await?;
+
+        if attr.kind == FileType::Directory {
+            return Err(AsyncFilesystemError::IsADirectory(attr.ino).into());
+        }
+        self.check_removable(req, parent, &attr).await?;
+
+        writable.remove(parent, name, attr.ino, attr.kind)?;
+        writable.touch(parent, SystemTime::now())?;
+
+        Ok(())
+    }
+
+    async fn rmdir(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+    ) -> Result<(), Self::Error> {
+        debug!("rmdir({}, {})", parent, name);
+
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        self.check_changeable(parent)?;
+        let (_, attr, _) = self.lookup(req, parent, name).await?;
+        self.check_changeable(attr.ino)?;
+
+        if attr.kind != FileType::Directory {
+            return Err(AsyncFilesystemError::NotADirectory(attr.ino).into());
+        }
+
+        if writable.has_children(attr.ino)? {
+            return Err(AsyncFilesystemError::NotEmpty(attr.ino).into());
+        }
+        self.check_removable(req, parent, &attr).await?;
+
+        writable.remove(parent, name, attr.ino, attr.kind)?;
+        writable.touch(parent, SystemTime::now())?;
+
+        Ok(())
+    }
+
+    async fn rename(
+        &self,
+        req: &RequestContext,
+        parent: u64,
+        name: &str,
+        newparent: u64,
+        newname: &str,
+        flags: u32,
+    ) -> Result<(), Self::Error> {
+        debug!(
+            "rename({}, {}, {}, {}, {})",
+            parent, name, newparent, newname, flags
         );
 
-        self.ctx
-            .sql(&query)
-            .await?
-            .collect()
+        let writable = self.writable()?;
+        let _lock = writable.lock().await;
+
+        if flags & libc::RENAME_EXCHANGE != 0 {
+            return Err(DatafusionFsError::NotImplemented);
+        }
+
+        self.check_changeable(parent)?;
+        self.check_changeable(newparent)?;
+
+        let (_, attr, _) = self.lookup(req, parent, name).await?;
+        self.check_changeable(attr.ino)?;
+        self.check_removable(req, parent, &attr).await?;
+        let is_dir = attr.kind == FileType::Directory;
+
+        // Moving a directory rewrites its `..`.
+        if is_dir && parent != newparent {
+            check_access(req, &attr, libc::W_OK)?;
+        }
+
+        if is_dir && parent != newparent {
+            self.check_not_within(req, attr.ino, newparent).await?;
+        }
+
+        match self.lookup(req, newparent, newname).await {
+            Ok(_) if flags & libc::RENAME_NOREPLACE != 0 => {
+                return Err(
+                    AsyncFilesystemError::AlreadyExists(newparent, newname.to_owned()).into(),
+                );
+            }
+            // Both names are links to the same file.
+            Ok((_, target, _)) if target.ino == attr.ino => return Ok(()),
+            Ok((_, target, _)) => {
+                self.check_changeable(target.ino)?;
+                self.check_removable(req, newparent, &target).await?;
+
+                match (is_dir, target.kind == FileType::Directory) {
+                    (true, false) => {
+                        return Err(AsyncFilesystemError::NotADirectory(target.ino).into())
+                    }
+                    (false, true) => {
+                        return Err(AsyncFilesystemError::IsADirectory(target.ino).into())
+                    }
+                    (true, true) if writable.has_children(target.ino)? => {
+                        return Err(AsyncFilesystemError::NotEmpty(target.ino).into())
+                    }
+                    _ => {}
+                }
+
+                writable.remove(newparent, newname, target.ino, target.kind)?;
+            }
+            Err(DatafusionFsError::NotFound) => {
+                let (_, dir) = self.getattr(req, newparent).await?;
+
+                if dir.kind != FileType::Directory {
+                    return Err(AsyncFilesystemError::NotADirectory(newparent).into());
+                }
+                check_access(req, &dir, libc::W_OK | libc::X_OK)?;
+            }
+            Err(e) => return Err(e),
+        }
+
+        writable.rename(parent, name, newparent, newname)?;
+
+        if is_dir && parent != newparent {
+            writable.reparent(attr.ino, newparent)?;
+        }
+
+        let now = SystemTime::now();
+        writable.touch(parent, now)?;
+        writable.touch(newparent, now)?;
+
+        Ok(())
+    }
+
+    async fn readlink(&self, _req: &RequestContext, ino: u64) -> Result<Vec<u8>, Self::Error> {
+        debug!("readlink({})", ino);
+
+        Ok(self.target(ino).await?.into_bytes())
+    }
+
+    async fn statfs(&self, _req: &RequestContext, ino: u64) -> Result<Statfs, Self::Error> {
+        debug!("statfs({})", ino);
+
+        let files = self.query(&self.queries.files, vec![]).await?;
+        let used = self.query(&self.queries.used_bytes, vec![]).await?;
+
+        let files = first_u64(&files, 0).unwrap_or(0);
+        let used = first_u64(&used, 0).unwrap_or(0);
+        // Read-only filesystems have no room left, as usual.
+        let free = match self.writable {
+            Some(_) => Statfs::FREE_BLOCKS,
+            None => 0,
+        };
+
+        Ok(Statfs {
+            blocks: used.div_ceil(BLOCK_SIZE) + free,
+            bfree: free,
+            bavail: free,
+            files,
+            bsize: BLOCK_SIZE as u32,
+            frsize: BLOCK_SIZE as u32,
+            ..Statfs::default()
+        })
+    }
+
+    async fn access(&self, req: &RequestContext, ino: u64, mask: i32) -> Result<(), Self::Error> {
+        debug!("access({}, {:o})", ino, mask);
+
+        let (_, attr) = self.getattr(req, ino).await?;
+
+        Ok(check_access(req, &attr, mask)?)
+    }
+
+    async fn getxattr(
+        &self,
+        _req: &RequestContext,
+        ino: u64,
+        name: &str,
+    ) -> Result<Vec<u8>, Self::Error> {
+        debug!("getxattr({}, {})", ino, name);
+
+        if self.is_virtual(ino) {
+            return Err(AsyncFilesystemError::NoSuchAttribute(ino, name.to_owned()).into());
+        }
+
+        if name == ID_XATTR {
+            return Ok(self.id(ino).await?.into_bytes());
+        }
+
+        self.xattrs(ino, Some(name))
             .await?
             .content(1)
             .flatten()
             .next()
-            .map(|c| c[offset as usize..size as usize].to_vec())
-            .ok_or(DatafusionFsError::NotFound)
+            .map(|v| v.to_vec())
+            .ok_or_else(|| AsyncFilesystemError::NoSuchAttribute(ino, name.to_owned()).into())
+    }
+
+    async fn listxattr(&self, _req: &RequestContext, ino: u64) -> Result<Vec<String>, Self::Error> {
+        debug!("listxattr({})", ino);
+
+        if self.is_virtual(ino) {
+            return Ok(vec![]);
+        }
+
+        // Also checks that the inode exists.
+        self.id(ino).await?;
+
+        let batches = self.xattrs(ino, None).await?;
+        let names = std::iter::once(ID_XATTR)
+            .chain(batches.names(0).flatten().filter(|n| *n != ID_XATTR))
+            .map(str::to_owned)
+            .collect();
+
+        Ok(names)
     }
 }
+
+/// Check that the caller can access `attr` with `mask`, see
+/// [`RequestContext::can_access`].
+fn check_access(
+    req: &RequestContext,
+    attr: &FileAttr,
+    mask: i32,
+) -> Result<(), AsyncFilesystemError> {
+    match req.can_access(attr, mask) {
+        true => Ok(()),
+        false => Err(AsyncFilesystemError::PermissionDenied(attr.ino)),
+    }
+}
+
+/// Check that the caller can change the attributes of `current` to `attr`, as
+/// `chmod`, `chown` and `utimensat` would.
+///
+/// Only the owner can change the mode, and only root the owner, while the owner
+/// can also change the group to its own. Others can set the times to the current
+/// time if they can write the file, which can't be told apart from explicit times
+/// here, so write access lets them set any time.
+fn check_setattr(
+    req: &RequestContext,
+    current: &FileAttr,
+    attr: &SetAttr,
+) -> Result<(), AsyncFilesystemError> {
+    let root = req.uid == 0;
+    let owner = root || req.uid == current.uid;
+    let denied = Err(AsyncFilesystemError::NotPermitted(current.ino));
+
+    if attr.mode.is_some() && !owner {
+        return denied;
+    }
+    if attr.uid.is_some_and(|uid| uid != current.uid) && !root {
+        return denied;
+    }
+    if attr.gid.is_some_and(|gid| gid != current.gid)
+        && !root
+        && !(owner && attr.gid == Some(req.gid))
+    {
+        return denied;
+    }
+    if (attr.atime.is_some() || attr.mtime.is_some()) && !owner {
+        return check_access(req, current, libc::W_OK);
+    }
+
+    Ok(())
+}
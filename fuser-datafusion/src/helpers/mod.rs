@@ -9,13 +9,21 @@ use datafusion::{
     common::cast::as_string_array,
     logical_expr::Volatility,
     physical_plan::functions::make_scalar_function,
-    prelude::{create_udf, SessionContext},
+    prelude::{create_udf, SessionConfig, SessionContext},
 };
 
 use crate::{BinArray, BINARY_TYPE};
 
+mod paths;
+
+pub use paths::{register_path_tables, PATHS_TABLE, SUBTREE_TABLE};
+
 pub fn create_context() -> SessionContext {
-    let ctx = SessionContext::new();
+    create_context_with_config(SessionConfig::new())
+}
+
+pub fn create_context_with_config(config: SessionConfig) -> SessionContext {
+    let ctx = SessionContext::with_config(config);
 
     ctx.register_udf(create_udf(
         "to_binary",
@@ -33,6 +41,10 @@ pub fn create_context() -> SessionContext {
         make_scalar_function(binary_size),
     ));
 
+    // Only fails without a default schema, where there is no metadata table to
+    // navigate either.
+    register_path_tables(&ctx).ok();
+
     ctx
 }
 
@@ -0,0 +1,385 @@
+use std::{any::Any, sync::Arc};
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, StringArray, UInt64Array},
+        compute::concat_batches,
+        datatypes::{DataType, Field, Schema, SchemaRef},
+        record_batch::RecordBatch,
+    },
+    common::ScalarValue,
+    datasource::{MemTable, TableProvider, TableType},
+    error::Result,
+    execution::context::SessionState,
+    logical_expr::{BinaryExpr, Operator, TableProviderFilterPushDown},
+    physical_plan::ExecutionPlan,
+    prelude::*,
+};
+use fuser_async::fuser::FUSE_ROOT_ID;
+use itertools::izip;
+
+use crate::{conversion::BatchesIterators, METADATA_TABLE};
+
+/// Name of the table of the paths of every inode.
+pub const PATHS_TABLE: &str = "paths";
+
+/// Name of the table of the descendants of every inode.
+pub const SUBTREE_TABLE: &str = "subtree";
+
+/// Paths reaching `PATH_MAX` can't be used, which also ends the recursion on
+/// directories looping through the metadata table.
+const PATH_MAX: usize = libc::PATH_MAX as usize;
+
+/// The entries of the metadata table other than `.` and `..`, as `child`,
+/// `parent_ino` and `name`.
+async fn entries(ctx: &SessionContext) -> Result<DataFrame> {
+    ctx.table(METADATA_TABLE)
+        .await?
+        .select(vec![
+            cast(col("ino"), DataType::UInt64).alias("child"),
+            cast(col("parent_ino"), DataType::UInt64).alias("parent_ino"),
+            col("name"),
+        ])?
+        .filter(
+            col("child")
+                .is_not_null()
+                .and(col("name").is_not_null())
+                .and(col("name").not_eq(lit(".")))
+                .and(col("name").not_eq(lit(".."))),
+        )
+}
+
+/// The paths of `ino`, one per hard link, climbing its ancestors a level at a
+/// time.
+async fn full_paths(ctx: &SessionContext, ino: u64) -> Result<Vec<String>> {
+    if ino == FUSE_ROOT_ID {
+        return Ok(vec!["/".to_owned()]);
+    }
+
+    let entries = entries(ctx).await?;
+    let mut r = vec![];
+    // The inodes left to climb from, with the end of the path below them.
+    let mut level = vec![(ino, String::new())];
+
+    while !level.is_empty() {
+        let inos = level.iter().map(|(ino, _)| lit(*ino)).collect();
+        let batches = (entries.clone())
+            .filter(col("child").in_list(inos, false))?
+            .collect()
+            .await?;
+
+        let mut next = vec![];
+        for (child, parent, name) in izip!(batches.inos(0), batches.inos(1), batches.names(2)) {
+            let (Some(child), Some(parent), Some(name)) = (child, parent, name) else {
+                continue;
+            };
+
+            for (_, below) in level.iter().filter(|(ino, _)| *ino == child) {
+                let path = format!("/{name}{below}");
+
+                match parent {
+                    _ if path.len() >= PATH_MAX => {}
+                    FUSE_ROOT_ID => r.push(path),
+                    _ => next.push((parent, path)),
+                }
+            }
+        }
+        level = next;
+    }
+
+    Ok(r)
+}
+
+/// The inode at `path` and its path without empty components, going down a name
+/// at a time.
+async fn resolve_path(ctx: &SessionContext, path: &str) -> Result<Option<(u64, String)>> {
+    let entries = entries(ctx).await?;
+    let names: Vec<_> = path.split('/').filter(|name| !name.is_empty()).collect();
+    let mut ino = FUSE_ROOT_ID;
+
+    for name in &names {
+        let batches = (entries.clone())
+            .filter(
+                col("parent_ino")
+                    .eq(lit(ino))
+                    .and(col("name").eq(lit(*name))),
+            )?
+            .collect()
+            .await?;
+
+        let child = batches.inos(0).flatten().next();
+        match child {
+            Some(child) => ino = child,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some((ino, format!("/{}", names.join("/")))))
+}
+
+/// The rows of `seeds`, with the columns of [`SubtreeTable`], followed by those of
+/// the descendants of their `ino`, a level at a time as a recursive query would.
+async fn descend(ctx: &SessionContext, seeds: RecordBatch) -> Result<Vec<RecordBatch>> {
+    let schema = seeds.schema();
+    let entries = entries(ctx).await?;
+    let mut r = vec![];
+    let mut level = vec![seeds];
+
+    while level.iter().any(|batch| batch.num_rows() > 0) {
+        let frontier = MemTable::try_new(schema.clone(), vec![level.clone()])?;
+        r.append(&mut level);
+
+        // Children of the root don't get a second slash.
+        let parent_path = when(col("path").eq(lit("/")), lit("")).otherwise(col("path"))?;
+        let batches = ctx
+            .read_table(Arc::new(frontier))?
+            .join(
+                entries.clone(),
+                JoinType::Inner,
+                &["ino"],
+                &["parent_ino"],
+                None,
+            )?
+            .select(vec![
+                col("root"),
+                col("root_path"),
+                col("child").alias("ino"),
+                concat(&[parent_path, lit("/"), col("name")]).alias("path"),
+            ])?
+            .filter(character_length(col("path")).lt(lit(PATH_MAX as i64)))?
+            .collect()
+            .await?;
+
+        level = batches
+            .into_iter()
+            .map(|batch| RecordBatch::try_new(schema.clone(), batch.columns().to_vec()))
+            .collect::<Result<_, _>>()?;
+    }
+
+    Ok(r)
+}
+
+/// The value compared to `column` by `filter`, as in `column = value`.
+fn equals<'a>(filter: &'a Expr, column: &str) -> Option<&'a ScalarValue> {
+    let Expr::BinaryExpr(BinaryExpr {
+        left,
+        op: Operator::Eq,
+        right,
+    }) = filter
+    else {
+        return None;
+    };
+
+    match (left.as_ref(), right.as_ref()) {
+        (Expr::Column(c), Expr::Literal(value)) | (Expr::Literal(value), Expr::Column(c))
+            if c.name == column =>
+        {
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+/// Inodes are written as `BIGINT` literals in queries.
+fn ino_equals(filter: &Expr, column: &str) -> Option<u64> {
+    match equals(filter, column)? {
+        ScalarValue::UInt64(ino) => *ino,
+        ScalarValue::Int64(ino) => u64::try_from((*ino)?).ok(),
+        _ => None,
+    }
+}
+
+fn path_equals<'a>(filter: &'a Expr, column: &str) -> Option<&'a str> {
+    match equals(filter, column)? {
+        ScalarValue::Utf8(path) | ScalarValue::LargeUtf8(path) => path.as_deref(),
+        _ => None,
+    }
+}
+
+/// The `(root, root_path, ino, path)` rows of `inos` and their paths.
+fn seeds<'a>(
+    schema: SchemaRef,
+    paths: impl IntoIterator<Item = (u64, &'a str)>,
+) -> Result<RecordBatch> {
+    let (inos, paths): (Vec<_>, Vec<_>) = paths.into_iter().unzip();
+    let inos: ArrayRef = Arc::new(UInt64Array::from(inos));
+    let paths: ArrayRef = Arc::new(StringArray::from(paths));
+
+    Ok(RecordBatch::try_new(
+        schema,
+        vec![inos.clone(), paths.clone(), inos, paths],
+    )?)
+}
+
+fn subtree_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("root", DataType::UInt64, false),
+        Field::new("root_path", DataType::Utf8, false),
+        Field::new("ino", DataType::UInt64, false),
+        Field::new("path", DataType::Utf8, false),
+    ]))
+}
+
+/// The `paths` table: the `ino` and `path` of every inode reachable from the root.
+struct PathsTable {
+    schema: SchemaRef,
+}
+
+#[async_trait]
+impl TableProvider for PathsTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::View
+    }
+
+    fn supports_filter_pushdown(&self, filter: &Expr) -> Result<TableProviderFilterPushDown> {
+        match ino_equals(filter, "ino").is_some() || path_equals(filter, "path").is_some() {
+            true => Ok(TableProviderFilterPushDown::Inexact),
+            false => Ok(TableProviderFilterPushDown::Unsupported),
+        }
+    }
+
+    async fn scan(
+        &self,
+        state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let ctx = SessionContext::with_state(state.clone());
+        let ino = filters.iter().find_map(|f| ino_equals(f, "ino"));
+        let path = filters.iter().find_map(|f| path_equals(f, "path"));
+
+        let rows = match (ino, path) {
+            (Some(ino), _) => {
+                let paths = full_paths(&ctx, ino).await?;
+                vec![seeds(
+                    subtree_schema(),
+                    paths.iter().map(|p| (ino, p.as_str())),
+                )?]
+            }
+            (None, Some(path)) => {
+                let found = resolve_path(&ctx, path).await?;
+                let found = found.iter().map(|(ino, path)| (*ino, path.as_str()));
+                vec![seeds(subtree_schema(), found)?]
+            }
+            (None, None) => {
+                let root = seeds(subtree_schema(), [(FUSE_ROOT_ID, "/")])?;
+                descend(&ctx, root).await?
+            }
+        };
+
+        let batches = rows
+            .iter()
+            .map(|batch| batch.project(&[2, 3]))
+            .collect::<Result<_, _>>()?;
+        let table = MemTable::try_new(self.schema.clone(), vec![batches])?;
+
+        table.scan(state, projection, &[], limit).await
+    }
+}
+
+/// The `subtree` table: every `ino` under each `root`, itself included, along with
+/// their paths `root_path` and `path`.
+struct SubtreeTable {
+    schema: SchemaRef,
+}
+
+#[async_trait]
+impl TableProvider for SubtreeTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::View
+    }
+
+    fn supports_filter_pushdown(&self, filter: &Expr) -> Result<TableProviderFilterPushDown> {
+        match ino_equals(filter, "root").is_some() || path_equals(filter, "root_path").is_some() {
+            true => Ok(TableProviderFilterPushDown::Inexact),
+            false => Ok(TableProviderFilterPushDown::Unsupported),
+        }
+    }
+
+    async fn scan(
+        &self,
+        state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let ctx = SessionContext::with_state(state.clone());
+        let root = filters.iter().find_map(|f| ino_equals(f, "root"));
+        let root_path = filters.iter().find_map(|f| path_equals(f, "root_path"));
+
+        let roots = match (root, root_path) {
+            (Some(root), _) => {
+                let paths = full_paths(&ctx, root).await?;
+                seeds(
+                    self.schema.clone(),
+                    paths.iter().map(|p| (root, p.as_str())),
+                )?
+            }
+            (None, Some(path)) => {
+                let found = resolve_path(&ctx, path).await?;
+                let found = found.iter().map(|(ino, path)| (*ino, path.as_str()));
+                seeds(self.schema.clone(), found)?
+            }
+            // Every inode is a root.
+            (None, None) => {
+                let root = seeds(self.schema.clone(), [(FUSE_ROOT_ID, "/")])?;
+                let batch = concat_batches(&self.schema, &descend(&ctx, root).await?)?;
+                let (inos, paths) = (batch.column(2), batch.column(3));
+
+                RecordBatch::try_new(
+                    self.schema.clone(),
+                    vec![inos.clone(), paths.clone(), inos.clone(), paths.clone()],
+                )?
+            }
+        };
+
+        let batches = descend(&ctx, roots).await?;
+        let table = MemTable::try_new(self.schema.clone(), vec![batches])?;
+
+        table.scan(state, projection, &[], limit).await
+    }
+}
+
+/// Register tables navigating the metadata table of `ctx` by path:
+///
+/// - `paths`: the `ino` and `path` of every inode, such as `/a/b`, with a row
+///   per hard link.
+/// - `subtree`: every `ino` under each `root`, itself included, along with their
+///   paths `root_path` and `path`.
+///
+/// DataFusion has neither recursive queries nor table functions, so the tables
+/// are computed a level of the tree at a time when they are scanned, as a
+/// recursive query would, following the changes of the metadata table. Equality
+/// filters on `paths.ino`, `paths.path`, `subtree.root` and `subtree.root_path`
+/// stand for the arguments of table functions: the recursion starts from the
+/// inode they name instead of going through the whole tree, as in
+/// `SELECT ino FROM subtree WHERE root_path = '/a/b'`.
+pub fn register_path_tables(ctx: &SessionContext) -> Result<()> {
+    let schema = subtree_schema();
+    let paths = PathsTable {
+        schema: Arc::new(schema.project(&[2, 3])?),
+    };
+
+    ctx.register_table(PATHS_TABLE, Arc::new(paths))?;
+    ctx.register_table(SUBTREE_TABLE, Arc::new(SubtreeTable { schema }))?;
+
+    Ok(())
+}
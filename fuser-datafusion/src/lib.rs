@@ -1,10 +1,18 @@
+mod control;
 mod conversion;
 pub mod errors;
+mod export;
 mod fs;
+mod queries;
 mod schemas;
+mod writable;
 
 pub mod helpers;
 pub mod parquet;
 
-pub use fs::{DatafusionFs, CONTENT_TABLE, METADATA_TABLE};
+pub use export::{ExportFormat, TableExportFs, TableExportHandle};
+pub use fs::{
+    DatafusionFs, DatafusionFsOptions, DatafusionHandle, CHUNKS_TABLE, CONTENT_TABLE,
+    CONTROL_DIR_INO, METADATA_TABLE, QUERY_DIR_INO, XATTRS_TABLE,
+};
 pub use schemas::*;
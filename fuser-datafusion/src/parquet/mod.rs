@@ -0,0 +1,107 @@
+//! Loading a [`DatafusionFs`](crate::DatafusionFs) dataset from parquet.
+//!
+//! Lookups filter the metadata table on `parent_ino` and `ino`, and reads filter the
+//! content on `ino`. Writing the files sorted by those columns keeps the row-group
+//! statistics tight, so that the loaded tables skip the row groups that can't match.
+
+mod snapshot;
+
+use datafusion::{
+    arrow::datatypes::{Field, Schema},
+    prelude::*,
+};
+
+use crate::{
+    conform_table, errors::DatafusionFsError, helpers::create_context_with_config, CHUNKS_SCHEMA,
+    CHUNKS_TABLE, CONTENT_SCHEMA, CONTENT_TABLE, METADATA_OPTIONAL_FIELDS, METADATA_SCHEMA,
+    METADATA_TABLE, XATTRS_SCHEMA, XATTRS_TABLE,
+};
+
+pub(crate) use snapshot::write_table;
+pub use snapshot::{snapshot, SnapshotOptions, SnapshotStats};
+
+/// Registers the tables of a dataset from parquet files or directories of them.
+///
+/// ```no_run
+/// # async fn f() -> Result<(), fuser_datafusion::errors::DatafusionFsError> {
+/// use fuser_datafusion::{parquet::ParquetLoader, DatafusionFs};
+///
+/// let ctx = ParquetLoader::new("dataset/metadata", "dataset/content.parquet")
+///     .load()
+///     .await?;
+/// let fs = DatafusionFs::new(ctx);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ParquetLoader {
+    metadata: String,
+    content: String,
+    chunks: Option<String>,
+    xattrs: Option<String>,
+}
+
+impl ParquetLoader {
+    pub fn new(metadata: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            metadata: metadata.into(),
+            content: content.into(),
+            chunks: None,
+            xattrs: None,
+        }
+    }
+
+    /// Also register the chunked content layout, see [`CHUNKS_TABLE`].
+    pub fn with_chunks(mut self, chunks: impl Into<String>) -> Self {
+        self.chunks = Some(chunks.into());
+        self
+    }
+
+    /// Also register extended attributes, see [`XATTRS_TABLE`].
+    pub fn with_xattrs(mut self, xattrs: impl Into<String>) -> Self {
+        self.xattrs = Some(xattrs.into());
+        self
+    }
+
+    /// Create a context with the tables registered and their schemas checked, see
+    /// [`conform_table`].
+    pub async fn load(&self) -> Result<SessionContext, DatafusionFsError> {
+        let config = SessionConfig::new()
+            .with_parquet_pruning(true)
+            .set_bool("datafusion.execution.parquet.pushdown_filters", true)
+            .set_bool("datafusion.execution.parquet.reorder_filters", true);
+        let ctx = create_context_with_config(config);
+
+        register(
+            &ctx,
+            METADATA_TABLE,
+            &self.metadata,
+            &METADATA_SCHEMA,
+            &METADATA_OPTIONAL_FIELDS,
+        )
+        .await?;
+        register(&ctx, CONTENT_TABLE, &self.content, &CONTENT_SCHEMA, &[]).await?;
+
+        if let Some(chunks) = &self.chunks {
+            register(&ctx, CHUNKS_TABLE, chunks, &CHUNKS_SCHEMA, &[]).await?;
+        }
+
+        if let Some(xattrs) = &self.xattrs {
+            register(&ctx, XATTRS_TABLE, xattrs, &XATTRS_SCHEMA, &[]).await?;
+        }
+
+        Ok(ctx)
+    }
+}
+
+async fn register(
+    ctx: &SessionContext,
+    table: &str,
+    path: &str,
+    expected: &Schema,
+    optional: &[Field],
+) -> Result<(), DatafusionFsError> {
+    ctx.register_parquet(table, path, ParquetReadOptions::default())
+        .await?;
+
+    conform_table(ctx, table, expected, optional).await
+}
@@ -0,0 +1,558 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, Metadata},
+    io::Read,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, StringArray, TimestampMicrosecondArray, UInt32Array, UInt64Array},
+        datatypes::{Schema, SchemaRef},
+        record_batch::RecordBatch,
+    },
+    error::{DataFusionError, Result},
+    parquet::{
+        arrow::ArrowWriter,
+        file::properties::{EnabledStatistics, WriterProperties},
+    },
+};
+use log::{debug, warn};
+
+use crate::{
+    errors::DatafusionFsError, BinArray, CHUNKS_SCHEMA, CHUNKS_TABLE, CONTENT_SCHEMA,
+    CONTENT_TABLE, METADATA_FULL_SCHEMA, METADATA_TABLE,
+};
+
+const ROOT_INO: u64 = 1;
+const METADATA_BATCH_ROWS: usize = 8192;
+const CONTENT_BATCH_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Largest file whose content fits in a single row of the content table, whose
+/// binary offsets are `i32` unless the `large-binary` feature is enabled.
+#[cfg(not(feature = "large-binary"))]
+const MAX_INLINE_SIZE: u64 = i32::MAX as u64;
+
+#[cfg(feature = "large-binary")]
+const MAX_INLINE_SIZE: u64 = i64::MAX as u64;
+
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotOptions {
+    /// Start a new parquet file once a table's current file holds this many bytes
+    /// (uncompressed). By default every table is written to a single file.
+    pub max_file_size: Option<u64>,
+    /// Stream regular files into rows of this many bytes in the chunks table,
+    /// see [`CHUNKS_TABLE`], instead of storing each of them in a single row of
+    /// the content table. Needed for files larger than 2 GiB without the
+    /// `large-binary` feature.
+    ///
+    /// Ignored by [`DatafusionFs::flush_to_parquet`](crate::DatafusionFs::flush_to_parquet).
+    pub chunk_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotStats {
+    /// Entries of the tree, not counting `.` and `..`.
+    pub entries: u64,
+    /// Distinct regular files, hard links counted once.
+    pub files: u64,
+    /// Total size of the regular files.
+    pub bytes: u64,
+}
+
+/// Write the tree under `source` as a dataset under `dest`, with the metadata and
+/// content tables in the `metadata` and `content` directories, and the chunks
+/// table in `chunks` if [`SnapshotOptions::chunk_size`] is set.
+///
+/// Inodes are assigned breadth-first from the root, so the metadata table is
+/// written sorted by `parent_ino` and the content and chunks tables by `ino`,
+/// which keeps their row-group statistics selective. Hard links share an inode,
+/// and every directory gets `.` and `..` entries, so the `ino` column of the
+/// metadata table is only roughly sorted.
+///
+/// The result can be loaded with [`ParquetLoader`](super::ParquetLoader), adding
+/// the chunks table with [`with_chunks`](super::ParquetLoader::with_chunks) and
+/// the same [`chunk_size`](crate::DatafusionFsOptions::chunk_size) if files
+/// were chunked.
+pub fn snapshot(
+    source: impl AsRef<Path>,
+    dest: impl AsRef<Path>,
+    options: &SnapshotOptions,
+) -> Result<SnapshotStats, DatafusionFsError> {
+    Ok(write_snapshot(source.as_ref(), dest.as_ref(), options)?)
+}
+
+/// Write `batches` as the parts of a table in `dir`, replacing the directory.
+///
+/// The parts are written next to it first, so that a failure leaves the previous
+/// ones in place.
+pub(crate) fn write_table(
+    dir: &Path,
+    schema: SchemaRef,
+    batches: &[RecordBatch],
+    options: &SnapshotOptions,
+) -> Result<()> {
+    let tmp = dir.with_extension("tmp");
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+
+    let mut writer = PartWriter::new(tmp.clone(), schema.clone(), options)?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.write(&RecordBatch::new_empty(schema))?;
+    writer.close()?;
+
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::rename(&tmp, dir)?;
+
+    Ok(())
+}
+
+struct Dir {
+    ino: u64,
+    id: String,
+    path: PathBuf,
+    metadata: Metadata,
+    parent: Option<Arc<Dir>>,
+}
+
+fn write_snapshot(source: &Path, dest: &Path, options: &SnapshotOptions) -> Result<SnapshotStats> {
+    let metadata_schema = METADATA_FULL_SCHEMA.clone();
+
+    let mut metadata =
+        PartWriter::new(dest.join(METADATA_TABLE), metadata_schema.clone(), options)?;
+    let mut content = PartWriter::new(dest.join(CONTENT_TABLE), CONTENT_SCHEMA.clone(), options)?;
+    let mut chunks = match options.chunk_size {
+        Some(0) => {
+            return Err(DataFusionError::Execution(
+                "chunk size must not be 0".to_owned(),
+            ))
+        }
+        Some(chunk_size) => Some((
+            chunk_size,
+            PartWriter::new(dest.join(CHUNKS_TABLE), CHUNKS_SCHEMA.clone(), options)?,
+            ChunkRows::default(),
+        )),
+        None => None,
+    };
+
+    let mut rows = MetadataRows::default();
+    let mut blobs = ContentRows::default();
+    let mut stats = SnapshotStats::default();
+
+    let content_batch_bytes = options
+        .max_file_size
+        .map_or(CONTENT_BATCH_BYTES, |max| max.min(CONTENT_BATCH_BYTES));
+
+    let mut links: HashMap<(u64, u64), u64> = HashMap::new();
+    let mut next_ino = ROOT_INO + 1;
+
+    let mut queue = VecDeque::from([Arc::new(Dir {
+        ino: ROOT_INO,
+        id: "/".to_owned(),
+        path: source.to_owned(),
+        metadata: fs::metadata(source)?,
+        parent: None,
+    })]);
+
+    while let Some(dir) = queue.pop_front() {
+        let parent = dir.parent.as_deref().unwrap_or(&dir);
+
+        rows.push(dir.ino, &dir.id, ".", dir.ino, &dir.metadata, None);
+        rows.push(
+            parent.ino,
+            &parent.id,
+            "..",
+            dir.ino,
+            &parent.metadata,
+            None,
+        );
+
+        let mut children = fs::read_dir(&dir.path)?.collect::<Result<Vec<_>, _>>()?;
+        children.sort_by_key(|c| c.file_name());
+
+        for child in children {
+            let Ok(name) = child.file_name().into_string() else {
+                warn!("Skipping {:?}: name is not valid utf8", child.path());
+                continue;
+            };
+
+            let path = child.path();
+            let meta = fs::symlink_metadata(&path)?;
+            let id = match dir.id.as_str() {
+                "/" => format!("/{}", name),
+                parent => format!("{}/{}", parent, name),
+            };
+
+            let (ino, new) = if meta.is_dir() || meta.nlink() == 1 {
+                (next_ino, true)
+            } else {
+                match links.get(&(meta.dev(), meta.ino())) {
+                    Some(ino) => (*ino, false),
+                    None => {
+                        links.insert((meta.dev(), meta.ino()), next_ino);
+                        (next_ino, true)
+                    }
+                }
+            };
+
+            if new {
+                next_ino += 1;
+            }
+
+            let target = match meta.file_type().is_symlink() {
+                true => fs::read_link(&path)?.to_str().map(str::to_owned),
+                false => None,
+            };
+
+            rows.push(ino, &id, &name, dir.ino, &meta, target);
+            stats.entries += 1;
+
+            if meta.is_dir() {
+                queue.push_back(Arc::new(Dir {
+                    ino,
+                    id: id.clone(),
+                    path,
+                    metadata: meta,
+                    parent: Some(dir.clone()),
+                }));
+            } else if meta.is_file() && new {
+                let size = match &mut chunks {
+                    Some((chunk_size, writer, chunk_rows)) => {
+                        let size = chunk_rows.push_file(
+                            ino,
+                            &path,
+                            *chunk_size,
+                            writer,
+                            content_batch_bytes,
+                        )?;
+                        blobs.push(ino, size, None);
+                        size
+                    }
+                    None => {
+                        if meta.len() > MAX_INLINE_SIZE {
+                            return Err(DataFusionError::Execution(format!(
+                                "{} is larger than {} bytes, snapshot it with a chunk size",
+                                path.display(),
+                                MAX_INLINE_SIZE
+                            )));
+                        }
+
+                        // Keep the contents of a batch within its binary offsets.
+                        if blobs.bytes + meta.len() > content_batch_bytes {
+                            content.write(&blobs.take()?)?;
+                        }
+
+                        let data = fs::read(&path)?;
+                        let size = data.len() as u64;
+                        blobs.push(ino, size, Some(data));
+                        size
+                    }
+                };
+                stats.files += 1;
+                stats.bytes += size;
+            }
+
+            if rows.len() >= METADATA_BATCH_ROWS {
+                metadata.write(&rows.take(&metadata_schema)?)?;
+            }
+
+            if blobs.bytes >= content_batch_bytes || blobs.len() >= METADATA_BATCH_ROWS {
+                content.write(&blobs.take()?)?;
+            }
+        }
+    }
+
+    metadata.write(&rows.take(&metadata_schema)?)?;
+    content.write(&blobs.take()?)?;
+    metadata.close()?;
+    content.close()?;
+
+    if let Some((_, mut writer, mut chunk_rows)) = chunks {
+        writer.write(&chunk_rows.take()?)?;
+        writer.close()?;
+    }
+
+    debug!(
+        "Snapshot of {} written to {}: {:?}",
+        source.display(),
+        dest.display(),
+        stats
+    );
+
+    Ok(stats)
+}
+
+fn kind(meta: &Metadata) -> &'static str {
+    let t = meta.file_type();
+
+    if t.is_dir() {
+        "Directory"
+    } else if t.is_symlink() {
+        "Symlink"
+    } else if t.is_socket() {
+        "Socket"
+    } else if t.is_char_device() {
+        "CharDevice"
+    } else if t.is_block_device() {
+        "BlockDevice"
+    } else if t.is_fifo() {
+        "NamePipe"
+    } else {
+        "RegularFile"
+    }
+}
+
+fn micros(secs: i64, nsecs: i64) -> i64 {
+    secs * 1_000_000 + nsecs / 1_000
+}
+
+#[derive(Default)]
+struct MetadataRows {
+    ino: Vec<u64>,
+    id: Vec<String>,
+    kind: Vec<&'static str>,
+    name: Vec<String>,
+    parent_ino: Vec<u64>,
+    atime: Vec<i64>,
+    mtime: Vec<i64>,
+    ctime: Vec<i64>,
+    target: Vec<Option<String>>,
+    mode: Vec<u32>,
+    uid: Vec<u32>,
+    gid: Vec<u32>,
+    crtime: Vec<Option<i64>>,
+}
+
+impl MetadataRows {
+    fn push(
+        &mut self,
+        ino: u64,
+        id: &str,
+        name: &str,
+        parent_ino: u64,
+        meta: &Metadata,
+        target: Option<String>,
+    ) {
+        self.ino.push(ino);
+        self.id.push(id.to_owned());
+        self.kind.push(kind(meta));
+        self.name.push(name.to_owned());
+        self.parent_ino.push(parent_ino);
+        self.atime.push(micros(meta.atime(), meta.atime_nsec()));
+        self.mtime.push(micros(meta.mtime(), meta.mtime_nsec()));
+        self.ctime.push(micros(meta.ctime(), meta.ctime_nsec()));
+        self.target.push(target);
+        self.mode.push(meta.mode() & 0o7777);
+        self.uid.push(meta.uid());
+        self.gid.push(meta.gid());
+        self.crtime.push(
+            meta.created()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_micros() as i64),
+        );
+    }
+
+    fn len(&self) -> usize {
+        self.ino.len()
+    }
+
+    fn take(&mut self, schema: &SchemaRef) -> Result<RecordBatch> {
+        let rows = std::mem::take(self);
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(rows.ino)),
+            Arc::new(StringArray::from(rows.id)),
+            Arc::new(StringArray::from(rows.kind)),
+            Arc::new(StringArray::from(rows.name)),
+            Arc::new(UInt64Array::from(rows.parent_ino)),
+            Arc::new(TimestampMicrosecondArray::from(rows.atime)),
+            Arc::new(TimestampMicrosecondArray::from(rows.mtime)),
+            Arc::new(TimestampMicrosecondArray::from(rows.ctime)),
+            Arc::new(StringArray::from(rows.target)),
+            Arc::new(UInt32Array::from(rows.mode)),
+            Arc::new(UInt32Array::from(rows.uid)),
+            Arc::new(UInt32Array::from(rows.gid)),
+            Arc::new(TimestampMicrosecondArray::from(rows.crtime)),
+        ];
+
+        Ok(RecordBatch::try_new(schema.clone(), columns)?)
+    }
+}
+
+#[derive(Default)]
+struct ContentRows {
+    ino: Vec<u64>,
+    size: Vec<u64>,
+    content: Vec<Option<Vec<u8>>>,
+    bytes: u64,
+}
+
+impl ContentRows {
+    /// A file of `size` bytes, with its `data` unless it is chunked.
+    fn push(&mut self, ino: u64, size: u64, data: Option<Vec<u8>>) {
+        self.bytes += data.as_ref().map_or(0, |d| d.len() as u64);
+        self.ino.push(ino);
+        self.size.push(size);
+        self.content.push(data);
+    }
+
+    fn len(&self) -> usize {
+        self.ino.len()
+    }
+
+    fn take(&mut self) -> Result<RecordBatch> {
+        let rows = std::mem::take(self);
+
+        let content: BinArray = rows.content.iter().map(|c| c.as_deref()).collect();
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(rows.ino)),
+            Arc::new(UInt64Array::from(rows.size)),
+            Arc::new(content),
+        ];
+
+        Ok(RecordBatch::try_new(CONTENT_SCHEMA.clone(), columns)?)
+    }
+}
+
+#[derive(Default)]
+struct ChunkRows {
+    ino: Vec<u64>,
+    chunk_index: Vec<u64>,
+    data: Vec<Vec<u8>>,
+    bytes: u64,
+}
+
+impl ChunkRows {
+    /// Read the file at `path` a chunk at a time, writing the rows to `writer`
+    /// whenever they hold `batch_bytes`. Returns the size of the file.
+    fn push_file(
+        &mut self,
+        ino: u64,
+        path: &Path,
+        chunk_size: u64,
+        writer: &mut PartWriter,
+        batch_bytes: u64,
+    ) -> Result<u64> {
+        let mut file = File::open(path)?;
+        let mut size = 0;
+
+        for chunk_index in 0.. {
+            let mut data = Vec::new();
+            (&mut file).take(chunk_size).read_to_end(&mut data)?;
+
+            if data.is_empty() {
+                break;
+            }
+
+            let full = data.len() as u64 == chunk_size;
+            size += data.len() as u64;
+            self.bytes += data.len() as u64;
+            self.ino.push(ino);
+            self.chunk_index.push(chunk_index);
+            self.data.push(data);
+
+            if self.bytes >= batch_bytes {
+                writer.write(&self.take()?)?;
+            }
+
+            // A short chunk is the end of the file.
+            if !full {
+                break;
+            }
+        }
+
+        Ok(size)
+    }
+
+    fn take(&mut self) -> Result<RecordBatch> {
+        let rows = std::mem::take(self);
+
+        let data: BinArray = rows.data.iter().map(|d| Some(d.as_slice())).collect();
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(rows.ino)),
+            Arc::new(UInt64Array::from(rows.chunk_index)),
+            Arc::new(data),
+        ];
+
+        Ok(RecordBatch::try_new(CHUNKS_SCHEMA.clone(), columns)?)
+    }
+}
+
+/// Writes a table as `part-NNNNN.parquet` files, starting a new one when the
+/// current file reaches the size limit.
+struct PartWriter {
+    dir: PathBuf,
+    schema: Arc<Schema>,
+    max_file_size: Option<u64>,
+    writer: Option<ArrowWriter<File>>,
+    parts: usize,
+    written: u64,
+}
+
+impl PartWriter {
+    fn new(dir: PathBuf, schema: Arc<Schema>, options: &SnapshotOptions) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            schema,
+            max_file_size: options.max_file_size,
+            writer: None,
+            parts: 0,
+            written: 0,
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        // Always write at least one file, so that the table can be registered.
+        if batch.num_rows() == 0 && self.parts > 0 {
+            return Ok(());
+        }
+
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let path = self.dir.join(format!("part-{:05}.parquet", self.parts));
+                let props = WriterProperties::builder()
+                    .set_statistics_enabled(EnabledStatistics::Page)
+                    .build();
+
+                self.parts += 1;
+                self.written = 0;
+                self.writer.insert(ArrowWriter::try_new(
+                    File::create(path)?,
+                    self.schema.clone(),
+                    Some(props),
+                )?)
+            }
+        };
+
+        writer.write(batch)?;
+        self.written += batch.get_array_memory_size() as u64;
+
+        if matches!(self.max_file_size, Some(max) if self.written >= max) {
+            self.close()?;
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+
+        Ok(())
+    }
+}
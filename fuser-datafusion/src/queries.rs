@@ -0,0 +1,200 @@
+use datafusion::{
+    arrow::datatypes::DataType, error::Result, logical_expr::LogicalPlan, prelude::*,
+    scalar::ScalarValue,
+};
+use tokio::sync::OnceCell;
+
+/// A `PREPARE` statement, planned on first use and executed with new parameter
+/// values afterwards.
+///
+/// Values are bound as literals in the plan, so names coming from the kernel
+/// cannot change the query.
+pub struct Prepared {
+    sql: &'static str,
+    plan: OnceCell<LogicalPlan>,
+}
+
+impl Prepared {
+    fn new(sql: &'static str) -> Self {
+        Self {
+            sql,
+            plan: OnceCell::new(),
+        }
+    }
+
+    pub async fn execute(
+        &self,
+        ctx: &SessionContext,
+        params: Vec<ScalarValue>,
+    ) -> Result<DataFrame> {
+        let plan = self
+            .plan
+            .get_or_try_init(|| async { ctx.state().create_logical_plan(self.sql).await })
+            .await?;
+
+        ctx.execute_logical_plan(plan.clone().with_param_values(params)?)
+            .await
+    }
+}
+
+/// The queries of [`DatafusionFs`](crate::DatafusionFs).
+///
+/// Attribute queries return every metadata column plus `size` and `nlink`, as
+/// expected by `BatchesIterators::file_attrs`.
+pub struct Queries {
+    pub content: Prepared,
+    pub chunks: Prepared,
+    pub getattr: Prepared,
+    pub lookup: Prepared,
+    pub readdir: Prepared,
+    pub readdirplus: Prepared,
+    /// Every metadata column, since `target` is optional.
+    pub target: Prepared,
+    pub id: Prepared,
+    pub xattr: Prepared,
+    pub xattrs: Prepared,
+    pub files: Prepared,
+    pub used_bytes: Prepared,
+}
+
+impl Queries {
+    pub fn new() -> Self {
+        Self {
+            content: Prepared::new(
+                r#"PREPARE content(BIGINT UNSIGNED) AS
+                SELECT size, content FROM content WHERE ino = $1 LIMIT 1"#,
+            ),
+            chunks: Prepared::new(
+                r#"PREPARE chunks(BIGINT UNSIGNED, BIGINT UNSIGNED, BIGINT UNSIGNED) AS
+                SELECT chunk_index, data FROM chunks
+                WHERE ino = $1 AND chunk_index >= $2 AND chunk_index <= $3
+                ORDER BY chunk_index"#,
+            ),
+            // Files count the entries sharing their ino as links. Directories count
+            // their entry in the parent, their own `.` and the `..` of each
+            // subdirectory. Every side is filtered by the ino, so that row groups
+            // can be pruned.
+            getattr: Prepared::new(
+                r#"PREPARE getattr(BIGINT UNSIGNED) AS
+                SELECT
+                m.*,
+                c.size,
+                CAST(CASE WHEN m.type = 'Directory'
+                    THEN 2 + COALESCE(d.subdirs, 0)
+                    ELSE COALESCE(l.links, 1)
+                END AS BIGINT UNSIGNED) AS nlink
+                FROM (SELECT * FROM metadata WHERE ino = $1 LIMIT 1) m
+                LEFT JOIN (SELECT ino, size FROM content WHERE ino = $1) c ON m.ino = c.ino
+                LEFT JOIN (
+                    SELECT ino, COUNT(*) AS links FROM metadata
+                    WHERE ino = $1 AND name <> '.' AND name <> '..'
+                    GROUP BY ino
+                ) l ON m.ino = l.ino
+                LEFT JOIN (
+                    SELECT parent_ino, COUNT(*) AS subdirs FROM metadata
+                    WHERE parent_ino = $1 AND type = 'Directory' AND name <> '.' AND name <> '..'
+                    GROUP BY parent_ino
+                ) d ON m.ino = d.parent_ino
+                LIMIT 1"#,
+            ),
+            // Only the ino, whose attributes are then queried with `getattr`: the
+            // content of the entry can't be pruned before its ino is known.
+            lookup: Prepared::new(
+                r#"PREPARE lookup(BIGINT UNSIGNED, VARCHAR) AS
+                SELECT ino FROM metadata
+                WHERE parent_ino = $1 AND name = $2 LIMIT 1"#,
+            ),
+            readdir: Prepared::new(
+                r#"PREPARE readdir(BIGINT UNSIGNED) AS
+                SELECT ino, name, type FROM metadata
+                WHERE parent_ino = $1
+                ORDER BY ino ASC NULLS FIRST"#,
+            ),
+            // Same link counts as `getattr`, aggregated for the whole listing.
+            // Every side of the joins is restricted to the entries of the directory
+            // before aggregating, as parameters are not bound inside `IN` subqueries.
+            readdirplus: Prepared::new(
+                r#"PREPARE readdirplus(BIGINT UNSIGNED) AS
+                SELECT
+                m.*,
+                c.size,
+                CAST(CASE WHEN m.type = 'Directory'
+                    THEN 2 + COALESCE(d.subdirs, 0)
+                    ELSE COALESCE(l.links, 1)
+                END AS BIGINT UNSIGNED) AS nlink
+                FROM (SELECT * FROM metadata WHERE parent_ino = $1) m
+                LEFT JOIN (
+                    SELECT content.ino, content.size FROM content
+                    JOIN (SELECT DISTINCT ino FROM metadata WHERE parent_ino = $1) e
+                    ON content.ino = e.ino
+                ) c ON m.ino = c.ino
+                LEFT JOIN (
+                    SELECT metadata.ino, COUNT(*) AS links FROM metadata
+                    JOIN (
+                        SELECT DISTINCT ino FROM metadata
+                        WHERE parent_ino = $1 AND type <> 'Directory'
+                    ) e ON metadata.ino = e.ino
+                    WHERE name <> '.' AND name <> '..'
+                    GROUP BY metadata.ino
+                ) l ON m.ino = l.ino
+                LEFT JOIN (
+                    SELECT metadata.parent_ino, COUNT(*) AS subdirs FROM metadata
+                    JOIN (
+                        SELECT DISTINCT ino FROM metadata
+                        WHERE parent_ino = $1 AND type = 'Directory'
+                    ) e ON metadata.parent_ino = e.ino
+                    WHERE type = 'Directory' AND name <> '.' AND name <> '..'
+                    GROUP BY metadata.parent_ino
+                ) d ON m.ino = d.parent_ino
+                ORDER BY m.ino ASC NULLS FIRST"#,
+            ),
+            target: Prepared::new(
+                r#"PREPARE target(BIGINT UNSIGNED) AS
+                SELECT * FROM metadata WHERE ino = $1 LIMIT 1"#,
+            ),
+            id: Prepared::new(
+                r#"PREPARE id(BIGINT UNSIGNED) AS
+                SELECT id FROM metadata WHERE ino = $1 LIMIT 1"#,
+            ),
+            xattr: Prepared::new(
+                r#"PREPARE xattr(BIGINT UNSIGNED, VARCHAR) AS
+                SELECT name, value FROM xattrs WHERE ino = $1 AND name = $2 LIMIT 1"#,
+            ),
+            xattrs: Prepared::new(
+                r#"PREPARE xattrs(BIGINT UNSIGNED) AS
+                SELECT name, value FROM xattrs WHERE ino = $1 ORDER BY name"#,
+            ),
+            // `.` and `..` rows point at existing inos, so they don't inflate the count.
+            files: Prepared::new(
+                r#"PREPARE files AS
+                SELECT CAST(COUNT(DISTINCT ino) AS BIGINT UNSIGNED) FROM metadata"#,
+            ),
+            used_bytes: Prepared::new(
+                r#"PREPARE used_bytes AS
+                SELECT SUM(size) FROM content"#,
+            ),
+        }
+    }
+}
+
+/// A virtual directory listing the rows of a query, see
+/// [`DatafusionFs::with_query_dir`](crate::DatafusionFs::with_query_dir).
+pub struct QueryDir {
+    pub name: String,
+    sql: String,
+}
+
+impl QueryDir {
+    pub fn new(name: String, sql: String) -> Self {
+        Self { name, sql }
+    }
+
+    /// The `ino`, `name` and `type` columns of the query, in that order.
+    pub async fn entries(&self, ctx: &SessionContext) -> Result<DataFrame> {
+        ctx.sql(&self.sql).await?.select(vec![
+            cast(col("ino"), DataType::UInt64).alias("ino"),
+            col("name"),
+            col("type"),
+        ])
+    }
+}
@@ -1,6 +1,12 @@
-use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::{
+    arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit},
+    common::Column,
+    prelude::*,
+};
 use lazy_static::lazy_static;
 
+use crate::errors::DatafusionFsError;
+
 #[cfg(not(feature = "large-binary"))]
 use datafusion::arrow::array::BinaryArray;
 
@@ -32,9 +38,143 @@ lazy_static! {
         Field::new("mtime", TIMESTAMP, false),
         Field::new("ctime", TIMESTAMP, false),
     ]));
+    /// Columns the metadata table may have after those of [`METADATA_SCHEMA`].
+    ///
+    /// `target` is only needed for symlinks. Missing columns or null values fall
+    /// back to [`DatafusionFsOptions`](crate::DatafusionFsOptions), and `crtime` to
+    /// `ctime`.
+    pub static ref METADATA_OPTIONAL_FIELDS: Vec<Field> = vec![
+        Field::new("target", DataType::Utf8, true),
+        Field::new("mode", DataType::UInt32, true),
+        Field::new("uid", DataType::UInt32, true),
+        Field::new("gid", DataType::UInt32, true),
+        Field::new("crtime", TIMESTAMP, true),
+    ];
+    /// [`METADATA_SCHEMA`] followed by every optional column.
+    pub static ref METADATA_FULL_SCHEMA: SchemaRef = SchemaRef::new(Schema::new(
+        METADATA_SCHEMA
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .chain(METADATA_OPTIONAL_FIELDS.iter().cloned())
+            .collect::<Vec<_>>()
+    ));
     pub static ref CONTENT_SCHEMA: SchemaRef = SchemaRef::new(Schema::new(vec![
         Field::new("ino", DataType::UInt64, false),
         Field::new("size", DataType::UInt64, false),
         Field::new("content", BINARY_TYPE, true),
     ]));
+    pub static ref CHUNKS_SCHEMA: SchemaRef = SchemaRef::new(Schema::new(vec![
+        Field::new("ino", DataType::UInt64, false),
+        Field::new("chunk_index", DataType::UInt64, false),
+        Field::new("data", BINARY_TYPE, false),
+    ]));
+    pub static ref XATTRS_SCHEMA: SchemaRef = SchemaRef::new(Schema::new(vec![
+        Field::new("ino", DataType::UInt64, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("value", BINARY_TYPE, false),
+    ]));
+}
+
+/// Check that `actual` has every column of `expected` with the same type, and the
+/// same type for the `optional` columns it has.
+pub fn validate_schema(
+    table: &str,
+    expected: &Schema,
+    optional: &[Field],
+    actual: &Schema,
+) -> Result<(), DatafusionFsError> {
+    let mismatch = |field: &Field| {
+        DatafusionFsError::SchemaMismatch(
+            table.to_owned(),
+            field.name().to_owned(),
+            field.data_type().clone(),
+        )
+    };
+
+    for field in expected.fields().iter() {
+        match actual.field_with_name(field.name()) {
+            Ok(f) if f.data_type() == field.data_type() => {}
+            _ => return Err(mismatch(field)),
+        }
+    }
+
+    for field in optional {
+        match actual.field_with_name(field.name()) {
+            Ok(f) if f.data_type() != field.data_type() => return Err(mismatch(field)),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a column of type `from` can be used as `to` after a cast.
+///
+/// Integers only widen: a cast to an unsigned type turns the values that don't fit,
+/// such as negative ones, into nulls, so signed and narrowing casts are mismatches.
+fn can_cast(from: &DataType, to: &DataType) -> bool {
+    use DataType::*;
+
+    matches!(
+        (from, to),
+        (UInt8 | UInt16, UInt32)
+            | (UInt8 | UInt16 | UInt32, UInt64)
+            | (Utf8 | LargeUtf8, Utf8 | LargeUtf8)
+            | (Binary | LargeBinary, Binary | LargeBinary)
+            | (Timestamp(_, _), Timestamp(_, _))
+    )
+}
+
+/// Check a registered table with [`validate_schema`], after casting the columns
+/// that can be safely converted to the expected type (e.g. `UInt32` to `UInt64`,
+/// or `Utf8` to `LargeUtf8`).
+///
+/// When casts are needed, the table is registered again as a view over the original.
+pub async fn conform_table(
+    ctx: &SessionContext,
+    table: &str,
+    expected: &Schema,
+    optional: &[Field],
+) -> Result<(), DatafusionFsError> {
+    let df = ctx.table(table).await?;
+    let actual: Schema = df.schema().clone().into();
+
+    let target = |name: &str| {
+        expected
+            .field_with_name(name)
+            .ok()
+            .or_else(|| optional.iter().find(|f| f.name() == name))
+            .map(|f| f.data_type())
+            .filter(|to| *to != actual.field_with_name(name).unwrap().data_type())
+    };
+
+    let mut casts = false;
+    let columns = actual
+        .fields()
+        .iter()
+        .map(|f| {
+            let column = Expr::Column(Column::from_name(f.name()));
+            match target(f.name()) {
+                Some(to) if can_cast(f.data_type(), to) => {
+                    casts = true;
+                    cast(column, to.clone()).alias(f.name())
+                }
+                _ => column,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if !casts {
+        return validate_schema(table, expected, optional, &actual);
+    }
+
+    let view = df.select(columns)?;
+    let schema: Schema = view.schema().clone().into();
+    validate_schema(table, expected, optional, &schema)?;
+
+    ctx.deregister_table(table)?;
+    ctx.register_table(table, view.into_view())?;
+
+    Ok(())
 }
@@ -0,0 +1,668 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::{
+        array::{
+            new_null_array, Array, ArrayRef, BooleanArray, StringArray, TimestampMicrosecondArray,
+            UInt32Array, UInt64Array,
+        },
+        compute::{
+            and, concat_batches, eq_scalar, eq_utf8_scalar, filter_record_batch, max, not, or,
+            prep_null_mask_filter,
+        },
+        datatypes::SchemaRef,
+        record_batch::RecordBatch,
+    },
+    datasource::{MemTable, TableProvider, TableType},
+    error::{DataFusionError, Result},
+    execution::context::SessionState,
+    logical_expr::Expr,
+    physical_plan::ExecutionPlan,
+    prelude::*,
+};
+use fuser_async::{async_filesystem::SetAttr, fuser::FileType};
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard};
+
+use crate::{
+    conversion::{column, file_type_name},
+    BinArray, CONTENT_SCHEMA, CONTENT_TABLE, METADATA_FULL_SCHEMA, METADATA_TABLE,
+};
+
+/// Rows and bytes up to which [`MutableTable`] merges small batches.
+const COMPACT_ROWS: usize = 8192;
+const COMPACT_BYTES: usize = 16 * 1024 * 1024;
+/// Batches that can be added before the first compaction.
+const COMPACT_SLACK: usize = 16;
+
+/// A table whose rows can change while plans scanning it are cached.
+///
+/// Every scan goes through a [`MemTable`] over the batches current at that time.
+/// Changes add small batches, which are merged once their number doubled since
+/// the last compaction.
+pub struct MutableTable {
+    schema: SchemaRef,
+    batches: RwLock<Vec<RecordBatch>>,
+    /// Number of batches after the last compaction.
+    compacted: AtomicUsize,
+    /// Number of changes so far.
+    version: AtomicU64,
+}
+
+impl MutableTable {
+    pub fn new(schema: SchemaRef, batches: Vec<RecordBatch>) -> Self {
+        Self {
+            schema,
+            compacted: AtomicUsize::new(batches.len()),
+            batches: RwLock::new(batches),
+            version: AtomicU64::new(0),
+        }
+    }
+
+    /// A number that changes whenever rows do.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    pub fn batches(&self) -> Vec<RecordBatch> {
+        self.batches.read().unwrap().clone()
+    }
+
+    pub fn append(&self, batch: RecordBatch) -> Result<()> {
+        if batch.num_rows() > 0 {
+            let mut batches = self.batches.write().unwrap();
+            batches.push(batch);
+            self.version.fetch_add(1, Ordering::Release);
+            self.compact(&mut batches)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merge consecutive batches up to [`COMPACT_ROWS`] rows and [`COMPACT_BYTES`]
+    /// bytes, if there are twice as many as after the last compaction.
+    fn compact(&self, batches: &mut Vec<RecordBatch>) -> Result<()> {
+        if batches.len() <= 2 * self.compacted.load(Ordering::Relaxed) + COMPACT_SLACK {
+            return Ok(());
+        }
+
+        let mut merged = vec![];
+        let mut run: Vec<&RecordBatch> = vec![];
+        let (mut rows, mut bytes) = (0, 0);
+
+        for batch in batches.iter() {
+            let size = batch.get_array_memory_size();
+
+            if !run.is_empty()
+                && (rows + batch.num_rows() > COMPACT_ROWS || bytes + size > COMPACT_BYTES)
+            {
+                merged.push(concat_batches(&self.schema, run.drain(..))?);
+                (rows, bytes) = (0, 0);
+            }
+
+            run.push(batch);
+            rows += batch.num_rows();
+            bytes += size;
+        }
+
+        if !run.is_empty() {
+            merged.push(concat_batches(&self.schema, run)?);
+        }
+
+        self.compacted.store(merged.len(), Ordering::Relaxed);
+        *batches = merged;
+
+        Ok(())
+    }
+
+    /// Rows for which `predicate` is true.
+    pub fn select(
+        &self,
+        predicate: impl Fn(&RecordBatch) -> Result<BooleanArray>,
+    ) -> Result<Vec<RecordBatch>> {
+        let mut rows = vec![];
+
+        for batch in self.batches.read().unwrap().iter() {
+            let mask = mask(predicate(batch)?);
+
+            if mask.true_count() > 0 {
+                rows.push(filter_record_batch(batch, &mask)?);
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// Replace the rows for which `predicate` is true by the result of `f` on them,
+    /// returning the number of rows replaced.
+    ///
+    /// Batches without matching rows are kept as they are.
+    pub fn update(
+        &self,
+        predicate: impl Fn(&RecordBatch) -> Result<BooleanArray>,
+        f: impl Fn(RecordBatch) -> Result<Option<RecordBatch>>,
+    ) -> Result<usize> {
+        let mut batches = self.batches.write().unwrap();
+
+        let mut kept = Vec::with_capacity(batches.len());
+        let mut updated = vec![];
+        let mut count = 0;
+
+        for batch in batches.iter() {
+            let mask = mask(predicate(batch)?);
+            let matches = mask.true_count();
+
+            if matches == 0 {
+                kept.push(batch.clone());
+                continue;
+            }
+
+            count += matches;
+
+            if matches < batch.num_rows() {
+                kept.push(filter_record_batch(batch, &not(&mask)?)?);
+            }
+
+            if let Some(batch) = f(filter_record_batch(batch, &mask)?)? {
+                updated.push(batch);
+            }
+        }
+
+        kept.extend(updated.into_iter().filter(|b| b.num_rows() > 0));
+        *batches = kept;
+        if count > 0 {
+            self.version.fetch_add(1, Ordering::Release);
+        }
+        self.compact(&mut batches)?;
+
+        Ok(count)
+    }
+
+    pub fn delete(
+        &self,
+        predicate: impl Fn(&RecordBatch) -> Result<BooleanArray>,
+    ) -> Result<usize> {
+        self.update(predicate, |_| Ok(None))
+    }
+}
+
+/// A filter from the result of a predicate, with nulls as false.
+fn mask(predicate: BooleanArray) -> BooleanArray {
+    match predicate.null_count() {
+        0 => predicate,
+        _ => prep_null_mask_filter(&predicate),
+    }
+}
+
+#[async_trait]
+impl TableProvider for MutableTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let table = MemTable::try_new(self.schema.clone(), vec![self.batches()])?;
+
+        table.scan(state, projection, filters, limit).await
+    }
+}
+
+fn u64_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a UInt64Array> {
+    column::<UInt64Array>(batch, name)
+        .ok_or_else(|| DataFusionError::Internal(format!("Column {} should be UInt64", name)))
+}
+
+fn str_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    column::<StringArray>(batch, name)
+        .ok_or_else(|| DataFusionError::Internal(format!("Column {} should be Utf8", name)))
+}
+
+/// Rows whose `ino` is `ino`.
+pub fn ino_is(ino: u64) -> impl Fn(&RecordBatch) -> Result<BooleanArray> {
+    move |batch| Ok(eq_scalar(u64_column(batch, "ino")?, ino)?)
+}
+
+/// The row of `name` in the directory `parent`.
+pub fn entry_is(parent: u64, name: &str) -> impl Fn(&RecordBatch) -> Result<BooleanArray> + '_ {
+    move |batch| {
+        let parent = eq_scalar(u64_column(batch, "parent_ino")?, parent)?;
+        let name = eq_utf8_scalar(str_column(batch, "name")?, name)?;
+
+        Ok(and(&parent, &name)?)
+    }
+}
+
+/// Rows of the directory `parent`, optionally without its `.` and `..`.
+pub fn children_of(parent: u64, dots: bool) -> impl Fn(&RecordBatch) -> Result<BooleanArray> {
+    move |batch| {
+        let children = eq_scalar(u64_column(batch, "parent_ino")?, parent)?;
+
+        if dots {
+            return Ok(children);
+        }
+
+        let names = str_column(batch, "name")?;
+        let dots = or(&eq_utf8_scalar(names, ".")?, &eq_utf8_scalar(names, "..")?)?;
+
+        Ok(and(&children, &not(&dots)?)?)
+    }
+}
+
+fn micros(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_micros() as i64,
+        Err(e) => -(e.duration().as_micros() as i64),
+    }
+}
+
+/// `batch` with the column `name` replaced by `array`.
+fn set_column(batch: &RecordBatch, name: &str, array: ArrayRef) -> Result<RecordBatch> {
+    let index = batch.schema().index_of(name)?;
+
+    let mut columns = batch.columns().to_vec();
+    columns[index] = array;
+
+    Ok(RecordBatch::try_new(batch.schema(), columns)?)
+}
+
+fn set_time(batch: &RecordBatch, name: &str, time: SystemTime) -> Result<RecordBatch> {
+    let values = vec![micros(time); batch.num_rows()];
+
+    set_column(
+        batch,
+        name,
+        Arc::new(TimestampMicrosecondArray::from(values)),
+    )
+}
+
+fn set_u32(batch: &RecordBatch, name: &str, value: u32) -> Result<RecordBatch> {
+    set_column(
+        batch,
+        name,
+        Arc::new(UInt32Array::from(vec![value; batch.num_rows()])),
+    )
+}
+
+/// `batch` with the columns of `schema`, those it doesn't have being null.
+fn with_schema(batch: &RecordBatch, schema: &SchemaRef) -> Result<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|f| match batch.column_by_name(f.name()) {
+            Some(column) => column.clone(),
+            None => new_null_array(f.data_type(), batch.num_rows()),
+        })
+        .collect();
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn content_batch(ino: u64, data: &[u8]) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(vec![ino])),
+        Arc::new(UInt64Array::from(vec![data.len() as u64])),
+        Arc::new(BinArray::from(vec![data])),
+    ];
+
+    Ok(RecordBatch::try_new(CONTENT_SCHEMA.clone(), columns)?)
+}
+
+/// A new row of the metadata table.
+pub struct Entry<'a> {
+    pub ino: u64,
+    pub kind: FileType,
+    pub name: &'a str,
+    pub parent: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Entry<'_> {
+    fn batch(&self, time: SystemTime) -> Result<RecordBatch> {
+        let time = micros(time);
+
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(vec![self.ino])),
+            Arc::new(StringArray::from(vec![self.ino.to_string()])),
+            Arc::new(StringArray::from(vec![file_type_name(self.kind)])),
+            Arc::new(StringArray::from(vec![self.name])),
+            Arc::new(UInt64Array::from(vec![self.parent])),
+            Arc::new(TimestampMicrosecondArray::from(vec![time])),
+            Arc::new(TimestampMicrosecondArray::from(vec![time])),
+            Arc::new(TimestampMicrosecondArray::from(vec![time])),
+            Arc::new(StringArray::from(vec![None::<&str>])),
+            Arc::new(UInt32Array::from(vec![self.mode & 0o7777])),
+            Arc::new(UInt32Array::from(vec![self.uid])),
+            Arc::new(UInt32Array::from(vec![self.gid])),
+            Arc::new(TimestampMicrosecondArray::from(vec![time])),
+        ];
+
+        Ok(RecordBatch::try_new(METADATA_FULL_SCHEMA.clone(), columns)?)
+    }
+}
+
+/// Content of a file open for writing, shared by its handles until the last one
+/// is released.
+pub struct OpenFile {
+    data: Mutex<Vec<u8>>,
+    dirty: AtomicBool,
+}
+
+impl OpenFile {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            data: Mutex::new(data),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.data.lock().unwrap().len() as u64
+    }
+
+    pub fn read(&self, offset: u64, size: u32) -> Vec<u8> {
+        let data = self.data.lock().unwrap();
+
+        let start = offset.min(data.len() as u64) as usize;
+        let end = start.saturating_add(size as usize).min(data.len());
+
+        data[start..end].to_vec()
+    }
+
+    pub fn write(&self, offset: u64, bytes: &[u8]) {
+        let mut data = self.data.lock().unwrap();
+
+        let end = offset as usize + bytes.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+
+        data[offset as usize..end].copy_from_slice(bytes);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    pub fn truncate(&self, size: u64) {
+        self.data.lock().unwrap().resize(size as usize, 0);
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    pub fn replace(&self, data: Vec<u8>) {
+        *self.data.lock().unwrap() = data;
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// The content, if it was changed since the last call.
+    pub fn take_dirty(&self) -> Option<Vec<u8>> {
+        let data = self.data.lock().unwrap();
+
+        match self.dirty.swap(false, Ordering::AcqRel) {
+            true => Some(data.clone()),
+            false => None,
+        }
+    }
+}
+
+/// In-memory metadata and content tables of a writable
+/// [`DatafusionFs`](crate::DatafusionFs), with the files open for writing.
+pub struct Writable {
+    pub metadata: Arc<MutableTable>,
+    pub content: Arc<MutableTable>,
+    next_ino: AtomicU64,
+    /// Serializes changes, which check and update several rows.
+    lock: AsyncMutex<()>,
+    files: Mutex<HashMap<u64, (Arc<OpenFile>, usize)>>,
+}
+
+impl Writable {
+    /// Copy the metadata and content tables of `ctx` to memory and register the
+    /// copies in their place.
+    ///
+    /// The metadata table gets every optional column, and other columns are dropped.
+    pub async fn new(ctx: &SessionContext) -> Result<Self> {
+        let metadata = ctx
+            .table(METADATA_TABLE)
+            .await?
+            .collect()
+            .await?
+            .iter()
+            .map(|batch| with_schema(batch, &METADATA_FULL_SCHEMA))
+            .collect::<Result<Vec<_>>>()?;
+        let content = ctx
+            .table(CONTENT_TABLE)
+            .await?
+            .collect()
+            .await?
+            .iter()
+            .map(|batch| with_schema(batch, &CONTENT_SCHEMA))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut last = 1;
+        for batch in &metadata {
+            last = last.max(max(u64_column(batch, "ino")?).unwrap_or(0));
+        }
+
+        let metadata = Arc::new(MutableTable::new(METADATA_FULL_SCHEMA.clone(), metadata));
+        let content = Arc::new(MutableTable::new(CONTENT_SCHEMA.clone(), content));
+
+        ctx.deregister_table(METADATA_TABLE)?;
+        ctx.register_table(METADATA_TABLE, metadata.clone())?;
+        ctx.deregister_table(CONTENT_TABLE)?;
+        ctx.register_table(CONTENT_TABLE, content.clone())?;
+
+        Ok(Self {
+            metadata,
+            content,
+            next_ino: AtomicU64::new(last + 1),
+            lock: AsyncMutex::new(()),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().await
+    }
+
+    pub fn allocate(&self) -> u64 {
+        self.next_ino.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn insert(&self, entry: &Entry, time: SystemTime) -> Result<()> {
+        self.metadata.append(entry.batch(time)?)?;
+
+        if entry.kind == FileType::RegularFile {
+            self.content.append(content_batch(entry.ino, &[])?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add the `..` entry of `dir`, with the attributes of `parent`.
+    pub fn link_parent(&self, dir: u64, parent: u64) -> Result<()> {
+        let rows = self.metadata.select(ino_is(parent))?;
+        let Some(row) = rows.first().map(|b| b.slice(0, 1)) else {
+            return Err(DataFusionError::Execution(format!(
+                "No entry for ino {}",
+                parent
+            )));
+        };
+
+        let row = set_column(&row, "name", Arc::new(StringArray::from(vec![".."])))?;
+        let row = set_column(&row, "parent_ino", Arc::new(UInt64Array::from(vec![dir])))?;
+        self.metadata.append(row)?;
+
+        Ok(())
+    }
+
+    pub fn has_children(&self, dir: u64) -> Result<bool> {
+        Ok(!self.metadata.select(children_of(dir, false))?.is_empty())
+    }
+
+    /// Remove the entry `name` of `parent` for `ino`, with the entries of a
+    /// directory or the content of a file without other links.
+    pub fn remove(&self, parent: u64, name: &str, ino: u64, kind: FileType) -> Result<()> {
+        self.metadata.delete(entry_is(parent, name))?;
+
+        if kind == FileType::Directory {
+            self.metadata.delete(children_of(ino, true))?;
+        } else if self.metadata.select(ino_is(ino))?.is_empty() {
+            self.content.delete(ino_is(ino))?;
+        }
+
+        Ok(())
+    }
+
+    /// Move the entry `name` of `parent` to `newname` in `newparent`.
+    pub fn rename(&self, parent: u64, name: &str, newparent: u64, newname: &str) -> Result<()> {
+        self.metadata.update(entry_is(parent, name), |rows| {
+            let n = rows.num_rows();
+            let rows = set_column(
+                &rows,
+                "parent_ino",
+                Arc::new(UInt64Array::from(vec![newparent; n])),
+            )?;
+            let rows = set_column(&rows, "name", Arc::new(StringArray::from(vec![newname; n])))?;
+
+            Ok(Some(rows))
+        })?;
+
+        Ok(())
+    }
+
+    /// Point the `..` entry of `dir` to `parent`.
+    pub fn reparent(&self, dir: u64, parent: u64) -> Result<()> {
+        self.metadata.delete(entry_is(dir, ".."))?;
+        self.link_parent(dir, parent)
+    }
+
+    /// Set the modification and change times of `ino` to `time`.
+    pub fn touch(&self, ino: u64, time: SystemTime) -> Result<()> {
+        self.metadata.update(ino_is(ino), |rows| {
+            Ok(Some(set_time(
+                &set_time(&rows, "mtime", time)?,
+                "ctime",
+                time,
+            )?))
+        })?;
+
+        Ok(())
+    }
+
+    /// Apply the attributes of `attr` other than the size to every row of `ino`.
+    ///
+    /// The change time is set to `time` unless given, as is the modification time
+    /// when the size changes.
+    pub fn set_attr(&self, ino: u64, attr: &SetAttr, time: SystemTime) -> Result<()> {
+        let mtime = attr.mtime.or(attr.size.map(|_| time));
+
+        self.metadata.update(ino_is(ino), |mut rows| {
+            if let Some(mode) = attr.mode {
+                rows = set_u32(&rows, "mode", mode & 0o7777)?;
+            }
+            if let Some(uid) = attr.uid {
+                rows = set_u32(&rows, "uid", uid)?;
+            }
+            if let Some(gid) = attr.gid {
+                rows = set_u32(&rows, "gid", gid)?;
+            }
+            if let Some(atime) = attr.atime {
+                rows = set_time(&rows, "atime", atime)?;
+            }
+            if let Some(mtime) = mtime {
+                rows = set_time(&rows, "mtime", mtime)?;
+            }
+            if let Some(crtime) = attr.crtime {
+                rows = set_time(&rows, "crtime", crtime)?;
+            }
+
+            Ok(Some(set_time(&rows, "ctime", attr.ctime.unwrap_or(time))?))
+        })?;
+
+        Ok(())
+    }
+
+    /// Replace the content of `ino`, unless it was removed.
+    pub fn commit(&self, ino: u64, data: &[u8], time: SystemTime) -> Result<()> {
+        if self.metadata.select(ino_is(ino))?.is_empty() {
+            return Ok(());
+        }
+
+        self.content.delete(ino_is(ino))?;
+        self.content.append(content_batch(ino, data)?)?;
+
+        self.touch(ino, time)
+    }
+
+    /// The open file of `ino`, if any.
+    pub fn file(&self, ino: u64) -> Option<Arc<OpenFile>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(&ino)
+            .map(|(file, _)| file.clone())
+    }
+
+    /// A new handle on the open file of `ino`, if any.
+    pub fn reopen(&self, ino: u64) -> Option<Arc<OpenFile>> {
+        let mut files = self.files.lock().unwrap();
+
+        files.get_mut(&ino).map(|(file, handles)| {
+            *handles += 1;
+            file.clone()
+        })
+    }
+
+    /// A new handle on the open file of `ino`, opening it with `data` if needed.
+    pub fn open(&self, ino: u64, data: Vec<u8>) -> Arc<OpenFile> {
+        let mut files = self.files.lock().unwrap();
+
+        let (file, handles) = files
+            .entry(ino)
+            .or_insert_with(|| (Arc::new(OpenFile::new(data)), 0));
+        *handles += 1;
+
+        file.clone()
+    }
+
+    /// Release a handle on the open file of `ino`, committing its changes first.
+    pub async fn release(&self, ino: u64, file: &OpenFile) -> Result<()> {
+        let _lock = self.lock().await;
+
+        if let Some(data) = file.take_dirty() {
+            self.commit(ino, &data, SystemTime::now())?;
+        }
+
+        let mut files = self.files.lock().unwrap();
+        if let Some((_, handles)) = files.get_mut(&ino) {
+            *handles -= 1;
+
+            if *handles == 0 {
+                files.remove(&ino);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, UInt64Array},
+        record_batch::RecordBatch,
+    },
+    datasource::MemTable,
+};
+use fuser_async::{async_filesystem::AsyncFilesystem, testing::TestDriver};
+use fuser_datafusion::{BinArray, DatafusionFs, DatafusionFsOptions, CHUNKS_SCHEMA, CHUNKS_TABLE};
+
+const CHUNK_SIZE: u64 = 5;
+
+/// The example dataset, with `hello.txt` split into chunks of `CHUNK_SIZE` bytes.
+async fn driver() -> TestDriver<DatafusionFs> {
+    let ctx = common::load_csv().await.unwrap();
+
+    let batches = b"Hello world!"
+        .chunks(CHUNK_SIZE as usize)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(UInt64Array::from(vec![2])),
+                Arc::new(UInt64Array::from(vec![i as u64])),
+                Arc::new(BinArray::from(vec![chunk])),
+            ];
+            RecordBatch::try_new(CHUNKS_SCHEMA.clone(), columns).unwrap()
+        })
+        .collect();
+    let chunks = MemTable::try_new(CHUNKS_SCHEMA.clone(), vec![batches]).unwrap();
+    ctx.register_table(CHUNKS_TABLE, Arc::new(chunks)).unwrap();
+
+    let fs = DatafusionFs::try_new(ctx)
+        .await
+        .unwrap()
+        .with_options(DatafusionFsOptions {
+            chunk_size: CHUNK_SIZE,
+            ..Default::default()
+        });
+
+    TestDriver::new(fs)
+}
+
+async fn read_at(driver: &TestDriver<DatafusionFs>, offset: i64, size: u32) -> Vec<u8> {
+    let fs = driver.fs();
+    let (handle, _) = fs.open(&driver.request(), 2, libc::O_RDONLY).await.unwrap();
+    let data = fs
+        .read(
+            &driver.request(),
+            2,
+            &handle,
+            offset,
+            size,
+            libc::O_RDONLY,
+            None,
+        )
+        .await
+        .unwrap();
+    fs.release(&driver.request(), 2, &handle, libc::O_RDONLY, None, false)
+        .await
+        .unwrap();
+
+    data
+}
+
+#[tokio::test]
+async fn reads_whole_file() {
+    let driver = driver().await;
+    driver.assert_content("/hello.txt", "Hello world!").await;
+
+    // Requests straddling every chunk boundary.
+    let driver = driver.with_read_chunk(3);
+    driver.assert_content("/hello.txt", "Hello world!").await;
+}
+
+#[tokio::test]
+async fn reads_across_chunks() {
+    let driver = driver().await;
+
+    assert_eq!(read_at(&driver, 0, 5).await, b"Hello");
+    assert_eq!(read_at(&driver, 5, 5).await, b" worl");
+    assert_eq!(read_at(&driver, 3, 4).await, b"lo w");
+    assert_eq!(read_at(&driver, 4, 8).await, b"o world!");
+    assert_eq!(read_at(&driver, 1, 100).await, b"ello world!");
+}
+
+#[tokio::test]
+async fn reads_past_the_end() {
+    let driver = driver().await;
+
+    assert_eq!(read_at(&driver, 10, 5).await, b"d!");
+    assert!(read_at(&driver, 12, 5).await.is_empty());
+    assert!(read_at(&driver, 15, 5).await.is_empty());
+    assert!(read_at(&driver, 1 << 40, 5).await.is_empty());
+    assert!(read_at(&driver, 3, 0).await.is_empty());
+}
@@ -0,0 +1,55 @@
+#![allow(dead_code)]
+
+use datafusion::{
+    arrow::datatypes::{DataType, Field, Schema},
+    prelude::*,
+};
+use fuser_datafusion::{helpers::create_context, CONTENT_TABLE, METADATA_SCHEMA, METADATA_TABLE};
+
+pub const DATA_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/data");
+
+/// The example dataset, loaded as in `examples/csv`.
+pub async fn load_csv() -> datafusion::error::Result<SessionContext> {
+    let ctx = create_context();
+
+    // The optional `target` column, for the symlink of the example.
+    let metadata_schema = Schema::new(
+        METADATA_SCHEMA
+            .fields()
+            .iter()
+            .map(|f| f.as_ref().clone())
+            .chain([Field::new("target", DataType::Utf8, true)])
+            .collect::<Vec<_>>(),
+    );
+
+    ctx.register_csv(
+        METADATA_TABLE,
+        &format!("{DATA_DIR}/metadata.csv"),
+        CsvReadOptions::default().schema(&metadata_schema),
+    )
+    .await?;
+
+    // The inodes are unsigned, as in the content table.
+    let content_schema = Schema::new(vec![
+        Field::new("ino", DataType::UInt64, false),
+        Field::new("content", DataType::Utf8, false),
+    ]);
+    let content = ctx
+        .read_csv(
+            &format!("{DATA_DIR}/content.csv"),
+            CsvReadOptions::default().schema(&content_schema),
+        )
+        .await?;
+
+    let to_binary = content.registry().udf("to_binary")?;
+    let binary_size = content.registry().udf("binary_size")?;
+
+    let content = content
+        .with_column("content", to_binary.call(vec![col("content")]))?
+        .with_column("size", binary_size.call(vec![col("content")]))?
+        .select(vec![col("ino"), col("size"), col("content")])?;
+
+    ctx.register_table(CONTENT_TABLE, content.into_view())?;
+
+    Ok(ctx)
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser_async::{
+    async_filesystem::{AsyncFilesystem, SetAttr},
+    errors::ToErrno,
+    testing::TestDriver,
+};
+use fuser_datafusion::DatafusionFs;
+
+const OTHER: (u32, u32) = (1000, 1000);
+const THIRD: (u32, u32) = (1001, 1001);
+
+async fn driver() -> TestDriver<DatafusionFs> {
+    let ctx = common::load_csv().await.unwrap();
+    let fs = DatafusionFs::try_new(ctx)
+        .await
+        .unwrap()
+        .with_control_dir("control");
+    TestDriver::new(fs)
+}
+
+/// A driver on the filesystem of `driver`, issuing requests as `(uid, gid)`.
+fn as_user(driver: &TestDriver<DatafusionFs>, (uid, gid): (u32, u32)) -> TestDriver<DatafusionFs> {
+    TestDriver::from_arc(driver.fs().clone()).with_user(uid, gid)
+}
+
+/// Create the query file `name` with `sql`, and run it.
+async fn create_query(driver: &TestDriver<DatafusionFs>, name: &str, sql: &str) -> u64 {
+    let fs = driver.fs();
+    let control = driver.assert_exists("/control").await;
+    let (_, attr, _, handle, _) = fs
+        .create(&driver.request(), control.ino, name, 0o644, 0o022, 0)
+        .await
+        .unwrap();
+
+    fs.write(
+        &driver.request(),
+        attr.ino,
+        &handle,
+        0,
+        sql.as_bytes(),
+        0,
+        0,
+        None,
+    )
+    .await
+    .unwrap();
+    fs.release(&driver.request(), attr.ino, &handle, 0, None, true)
+        .await
+        .unwrap();
+
+    attr.ino
+}
+
+#[tokio::test]
+async fn lets_everyone_run_queries() {
+    let driver = driver().await;
+    let control = driver.assert_exists("/control").await;
+    assert_eq!(control.perm, 0o1777);
+
+    let other = as_user(&driver, OTHER);
+    let ino = create_query(
+        &other,
+        "names.sql",
+        "SELECT name FROM metadata WHERE ino = 2",
+    )
+    .await;
+
+    let attr = other.assert_exists("/control/names.sql").await;
+    assert_eq!((attr.ino, attr.uid, attr.gid), (ino, OTHER.0, OTHER.1));
+    let result = other.assert_exists("/control/names.csv").await;
+    assert_eq!((result.uid, result.gid), OTHER);
+    other
+        .assert_content("/control/names.csv", "name\nhello.txt\n")
+        .await;
+}
+
+#[tokio::test]
+async fn keeps_queries_of_others() {
+    let driver = driver().await;
+    let other = as_user(&driver, OTHER);
+    let third = as_user(&driver, THIRD);
+    let ino = create_query(&other, "mine.sql", "SELECT 1").await;
+
+    let e = third
+        .fs()
+        .open(&third.request(), ino, libc::O_WRONLY)
+        .await
+        .map(drop)
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EACCES);
+
+    let control = third.assert_exists("/control").await;
+    let e = third
+        .fs()
+        .unlink(&third.request(), control.ino, "mine.sql")
+        .await
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EPERM);
+
+    other
+        .fs()
+        .unlink(&other.request(), control.ino, "mine.sql")
+        .await
+        .unwrap();
+    other.assert_not_found("/control/mine.sql").await;
+}
+
+#[tokio::test]
+async fn rejects_changes_of_modes_and_owners() {
+    let driver = driver().await;
+    let ino = create_query(&driver, "q.sql", "SELECT 1").await;
+    let attr = driver.assert_exists("/control/q.sql").await;
+
+    for change in [
+        SetAttr {
+            mode: Some(0o600),
+            ..Default::default()
+        },
+        SetAttr {
+            uid: Some(OTHER.0),
+            ..Default::default()
+        },
+        SetAttr {
+            gid: Some(OTHER.1),
+            ..Default::default()
+        },
+    ] {
+        let e = driver
+            .fs()
+            .setattr(&driver.request(), ino, None, change)
+            .await
+            .unwrap_err();
+        assert_eq!(e.errno(), libc::EPERM);
+    }
+
+    // Unchanged modes and owners are accepted, as for `cp -p`.
+    let unchanged = SetAttr {
+        mode: Some(attr.perm as u32),
+        uid: Some(attr.uid),
+        ..Default::default()
+    };
+    driver
+        .fs()
+        .setattr(&driver.request(), ino, None, unchanged)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn updates_the_mtime_of_queries() {
+    let driver = driver().await;
+    let fs = driver.fs();
+    let ino = create_query(&driver, "q.sql", "SELECT 1").await;
+
+    let epoch = SetAttr {
+        mtime: Some(UNIX_EPOCH),
+        ..Default::default()
+    };
+    let (_, attr) = fs
+        .setattr(&driver.request(), ino, None, epoch)
+        .await
+        .unwrap();
+    assert_eq!(attr.mtime, UNIX_EPOCH);
+
+    let (handle, _) = fs
+        .open(&driver.request(), ino, libc::O_WRONLY)
+        .await
+        .unwrap();
+    fs.write(&driver.request(), ino, &handle, 0, b"SELECT 2", 0, 0, None)
+        .await
+        .unwrap();
+    let (_, attr) = fs.getattr(&driver.request(), ino).await.unwrap();
+    assert!(attr.mtime > UNIX_EPOCH + Duration::from_secs(1));
+    fs.release(&driver.request(), ino, &handle, 0, None, true)
+        .await
+        .unwrap();
+
+    driver
+        .assert_content("/control/q.csv", "Int64(2)\n2\n")
+        .await;
+}
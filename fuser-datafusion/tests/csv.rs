@@ -0,0 +1,311 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use datafusion::datasource::MemTable;
+use fuser_async::{
+    async_filesystem::AsyncFilesystem,
+    errors::ToErrno,
+    fuser::{FileAttr, FileType},
+    testing::{TestDriver, ROOT_INO},
+};
+use fuser_datafusion::{DatafusionFs, DatafusionFsOptions, METADATA_SCHEMA, METADATA_TABLE};
+
+/// Names and attributes listed by `readdirplus` from `offset`.
+async fn readdirplus(
+    driver: &TestDriver<DatafusionFs>,
+    ino: u64,
+    offset: i64,
+) -> Vec<(i64, String, FileAttr)> {
+    let fs = driver.fs();
+    let (handle, _) = fs.opendir(&driver.request(), ino, 0).await.unwrap();
+    let entries = fs
+        .readdirplus(&driver.request(), ino, &handle, offset)
+        .await
+        .unwrap();
+    fs.releasedir(&driver.request(), ino, &handle, 0)
+        .await
+        .unwrap();
+
+    entries
+        .into_iter()
+        .map(|(offset, name, _, attr, _)| (offset, name, attr))
+        .collect()
+}
+
+async fn driver() -> TestDriver<DatafusionFs> {
+    let ctx = common::load_csv().await.unwrap();
+    TestDriver::new(DatafusionFs::try_new(ctx).await.unwrap())
+}
+
+#[tokio::test]
+async fn lookup() {
+    let driver = driver().await;
+
+    let root = driver.assert_exists("/").await;
+    assert_eq!(root.ino, ROOT_INO);
+    assert_eq!(root.kind, FileType::Directory);
+
+    let file = driver.assert_exists("/hello.txt").await;
+    assert_eq!(file.ino, 2);
+    assert_eq!(file.kind, FileType::RegularFile);
+    assert_eq!(file.size, 12);
+
+    let link = driver.assert_exists("/hello.lnk").await;
+    assert_eq!(link.kind, FileType::Symlink);
+
+    driver.assert_not_found("/missing.txt").await;
+    driver.assert_not_found("/hello.txt/child").await;
+}
+
+#[tokio::test]
+async fn readdir() {
+    let driver = driver().await;
+
+    driver
+        .assert_dir_entries("/", &["hello.txt", "hello.lnk"])
+        .await;
+
+    let entries = driver.readdir(ROOT_INO).await.unwrap();
+    let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert!(names.contains(&"."));
+    assert!(names.contains(&".."));
+}
+
+#[tokio::test]
+async fn names_with_quotes() {
+    let ctx = common::load_csv().await.unwrap();
+
+    // Copies of hello.txt, named with the quotes of SQL strings and identifiers.
+    let metadata = ctx
+        .sql(
+            r#"SELECT * FROM metadata
+            UNION ALL
+            SELECT CAST(4 AS BIGINT UNSIGNED), id, type, 'it''s.txt', parent_ino, atime, mtime,
+                ctime, target
+            FROM metadata WHERE name = 'hello.txt'
+            UNION ALL
+            SELECT CAST(5 AS BIGINT UNSIGNED), id, type, 'say "hi".txt', parent_ino, atime,
+                mtime, ctime, target
+            FROM metadata WHERE name = 'hello.txt'"#,
+        )
+        .await
+        .unwrap();
+    // Collected rather than a view, whose constant names DataFusion filters out
+    // of the CSV scan without evaluating them.
+    let schema = Arc::new(metadata.schema().into());
+    let batches = metadata.collect().await.unwrap();
+    ctx.deregister_table(METADATA_TABLE).unwrap();
+    ctx.register_table(
+        METADATA_TABLE,
+        Arc::new(MemTable::try_new(schema, vec![batches]).unwrap()),
+    )
+    .unwrap();
+    let driver = TestDriver::new(DatafusionFs::try_new(ctx).await.unwrap());
+
+    assert_eq!(driver.assert_exists("/it's.txt").await.ino, 4);
+    assert_eq!(driver.assert_exists("/say \"hi\".txt").await.ino, 5);
+    driver.assert_not_found("/it").await;
+    driver.assert_not_found("/say ").await;
+    driver.assert_not_found("/' OR '1'='1").await;
+
+    driver
+        .assert_dir_entries(
+            "/",
+            &["hello.txt", "hello.lnk", "it's.txt", "say \"hi\".txt"],
+        )
+        .await;
+}
+
+#[tokio::test]
+async fn readdir_pages() {
+    // One entry per reply, resumed each time from the offset of the last one.
+    let driver = driver().await.with_readdir_page(1);
+
+    driver
+        .assert_dir_entries("/", &["hello.txt", "hello.lnk"])
+        .await;
+}
+
+#[tokio::test]
+async fn read() {
+    let driver = driver().await;
+
+    driver.assert_content("/hello.txt", "Hello world!").await;
+
+    let driver = driver.with_read_chunk(5);
+    driver.assert_content("/hello.txt", "Hello world!").await;
+}
+
+#[tokio::test]
+async fn readlink() {
+    let driver = driver().await;
+
+    let link = driver.assert_exists("/hello.lnk").await;
+    let target = driver
+        .fs()
+        .readlink(&driver.request(), link.ino)
+        .await
+        .unwrap();
+    assert_eq!(target, b"hello.txt");
+}
+
+#[tokio::test]
+async fn without_target() {
+    let ctx = common::load_csv().await.unwrap();
+
+    let columns: Vec<_> = METADATA_SCHEMA
+        .fields()
+        .iter()
+        .map(|f| f.name().as_str())
+        .collect();
+    let metadata = ctx
+        .table(METADATA_TABLE)
+        .await
+        .unwrap()
+        .select_columns(&columns)
+        .unwrap();
+    ctx.deregister_table(METADATA_TABLE).unwrap();
+    ctx.register_table(METADATA_TABLE, metadata.into_view())
+        .unwrap();
+
+    let driver = TestDriver::new(DatafusionFs::try_new(ctx).await.unwrap());
+    driver.assert_content("/hello.txt", "Hello world!").await;
+
+    let link = driver.assert_exists("/hello.lnk").await;
+    let e = driver
+        .fs()
+        .readlink(&driver.request(), link.ino)
+        .await
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EINVAL);
+}
+
+#[tokio::test]
+async fn readdirplus_matches_getattr() {
+    let driver = driver().await;
+
+    let entries = readdirplus(&driver, ROOT_INO, 0).await;
+    let mut names: Vec<_> = entries.iter().map(|(_, name, _)| name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, [".", "..", "hello.lnk", "hello.txt"]);
+
+    for (_, name, attr) in &entries {
+        assert_eq!(
+            *attr,
+            driver.getattr(attr.ino).await.unwrap(),
+            "attributes of {name}"
+        );
+    }
+
+    // Resumes after the entry at the given offset.
+    let (offset, _, _) = &entries[1];
+    assert_eq!(readdirplus(&driver, ROOT_INO, *offset).await, entries[2..]);
+}
+
+#[tokio::test]
+async fn readdirplus_with_reordered_columns() {
+    let ctx = common::load_csv().await.unwrap();
+
+    let metadata = ctx.table(METADATA_TABLE).await.unwrap();
+    let mut columns: Vec<_> = metadata
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| f.name().clone())
+        .collect();
+    columns.reverse();
+    let columns: Vec<_> = columns.iter().map(String::as_str).collect();
+
+    let metadata = metadata.select_columns(&columns).unwrap();
+    ctx.deregister_table(METADATA_TABLE).unwrap();
+    ctx.register_table(METADATA_TABLE, metadata.into_view())
+        .unwrap();
+
+    let driver = TestDriver::new(DatafusionFs::try_new(ctx).await.unwrap());
+
+    let mut entries: Vec<_> = readdirplus(&driver, ROOT_INO, 0)
+        .await
+        .into_iter()
+        .map(|(_, name, attr)| (name, attr.ino))
+        .collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        [
+            (".".to_owned(), 1),
+            ("..".to_owned(), 1),
+            ("hello.lnk".to_owned(), 3),
+            ("hello.txt".to_owned(), 2)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn query_dir_entries_expire() {
+    let ctx = common::load_csv().await.unwrap();
+    let fs = DatafusionFs::try_new(ctx)
+        .await
+        .unwrap()
+        .with_query_dir(
+            "files",
+            "SELECT ino, name, type FROM metadata WHERE type = 'RegularFile' ORDER BY name",
+        )
+        .with_options(DatafusionFsOptions {
+            query_dir_ttl: Duration::from_millis(100),
+            ..Default::default()
+        });
+    let driver = TestDriver::new(fs);
+    let fs = driver.fs();
+
+    let (ttl, _, _) = fs
+        .lookup(&driver.request(), ROOT_INO, "hello.txt")
+        .await
+        .unwrap();
+    assert!(ttl > Duration::from_millis(100));
+
+    let files = driver.assert_exists("/files").await;
+    let (ttl, attr, _) = fs
+        .lookup(&driver.request(), files.ino, "hello.txt")
+        .await
+        .unwrap();
+    assert_eq!((ttl, attr.ino), (Duration::from_millis(100), 2));
+
+    let (handle, _) = fs.opendir(&driver.request(), files.ino, 0).await.unwrap();
+    let entries = fs
+        .readdirplus(&driver.request(), files.ino, &handle, 0)
+        .await
+        .unwrap();
+    assert!(entries
+        .iter()
+        .all(|(_, _, ttl, _, _)| *ttl <= Duration::from_millis(100)));
+}
+
+#[tokio::test]
+async fn entries_use_the_configured_ttl() {
+    let ctx = common::load_csv().await.unwrap();
+    let ttl = Duration::from_secs(5);
+    let fs = DatafusionFs::try_new(ctx)
+        .await
+        .unwrap()
+        .with_options(DatafusionFsOptions {
+            ttl,
+            ..Default::default()
+        });
+    let driver = TestDriver::new(fs);
+    let fs = driver.fs();
+
+    assert_eq!(fs.getattr(&driver.request(), 2).await.unwrap().0, ttl);
+    let (entry_ttl, _, _) = fs
+        .lookup(&driver.request(), ROOT_INO, "hello.txt")
+        .await
+        .unwrap();
+    assert_eq!(entry_ttl, ttl);
+
+    let (handle, _) = fs.opendir(&driver.request(), ROOT_INO, 0).await.unwrap();
+    let entries = fs
+        .readdirplus(&driver.request(), ROOT_INO, &handle, 0)
+        .await
+        .unwrap();
+    assert!(entries.iter().all(|(_, _, t, _, _)| *t <= ttl));
+}
@@ -0,0 +1,218 @@
+use std::{
+    any::Any,
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use async_trait::async_trait;
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, StringArray, UInt64Array},
+        compute::concat_batches,
+        datatypes::SchemaRef,
+        record_batch::RecordBatch,
+    },
+    datasource::{MemTable, TableProvider, TableType},
+    error::Result,
+    execution::context::SessionState,
+    logical_expr::Expr,
+    parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder,
+    physical_plan::ExecutionPlan,
+    prelude::SessionContext,
+};
+use fuser_async::{async_filesystem::AsyncFilesystem, testing::TestDriver};
+use fuser_datafusion::{helpers::create_context, TableExportFs};
+
+/// More than a row group of the exported Parquet files.
+const ROWS: u64 = 100_000;
+
+fn numbers() -> RecordBatch {
+    let ids: ArrayRef = Arc::new(UInt64Array::from_iter_values(0..ROWS));
+    let names: ArrayRef = Arc::new(StringArray::from_iter_values(
+        (0..ROWS).map(|i| format!("n{i}")),
+    ));
+
+    RecordBatch::try_from_iter([("id", ids), ("name", names)]).unwrap()
+}
+
+/// [`numbers`], counting its scans.
+struct Numbers {
+    table: MemTable,
+    scans: AtomicUsize,
+}
+
+impl Numbers {
+    fn new() -> Self {
+        let batch = numbers();
+        // Several batches, to export them a range at a time.
+        let batches = (0..ROWS as usize)
+            .step_by(8192)
+            .map(|i| batch.slice(i, 8192.min(ROWS as usize - i)))
+            .collect();
+
+        Self {
+            table: MemTable::try_new(batch.schema(), vec![batches]).unwrap(),
+            scans: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl TableProvider for Numbers {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.table.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &SessionState,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.table.scan(state, projection, filters, limit).await
+    }
+}
+
+fn driver_with(ctx: &SessionContext) -> TestDriver<TableExportFs> {
+    TestDriver::new(TableExportFs::new(ctx.clone())).with_read_chunk(128 * 1024)
+}
+
+fn driver() -> TestDriver<TableExportFs> {
+    let ctx = create_context();
+    ctx.register_table("numbers", Arc::new(Numbers::new()))
+        .unwrap();
+
+    driver_with(&ctx)
+}
+
+#[tokio::test]
+async fn reads_parquet_from_the_footer() {
+    let driver = driver();
+    let fs = driver.fs();
+    let attr = driver
+        .assert_exists("/datafusion/public/numbers/data.parquet")
+        .await;
+    assert!(attr.size > 0);
+
+    // Parquet readers start with the length of the metadata and the magic number.
+    let (handle, _) = fs
+        .open(&driver.request(), attr.ino, libc::O_RDONLY)
+        .await
+        .unwrap();
+    let footer = fs
+        .read(
+            &driver.request(),
+            attr.ino,
+            &handle,
+            attr.size as i64 - 8,
+            8,
+            libc::O_RDONLY,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(&footer[4..], b"PAR1");
+    fs.release(
+        &driver.request(),
+        attr.ino,
+        &handle,
+        libc::O_RDONLY,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+
+    let data = driver.read(attr.ino).await.unwrap();
+    assert_eq!(data.len() as u64, attr.size);
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&data).unwrap();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap();
+    let batches: Vec<_> = reader.map(Result::unwrap).collect();
+    let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+    assert_eq!(batch, numbers());
+}
+
+#[tokio::test]
+async fn reads_csv_in_ranges() {
+    let driver = driver();
+    let fs = driver.fs();
+    let attr = driver
+        .assert_exists("/datafusion/public/numbers/data.csv")
+        .await;
+
+    let data = driver.read(attr.ino).await.unwrap();
+    assert_eq!(data.len() as u64, attr.size);
+    let text = String::from_utf8(data.clone()).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+
+    assert_eq!(lines.len() as u64, ROWS + 1);
+    assert_eq!(lines[..3], ["id,name", "0,n0", "1,n1"]);
+    assert_eq!(lines.last(), Some(&"99999,n99999"));
+
+    // Seeking forward and backward reads from the same export.
+    let (handle, _) = fs
+        .open(&driver.request(), attr.ino, libc::O_RDONLY)
+        .await
+        .unwrap();
+    for offset in [1_000_000, 10] {
+        let range = fs
+            .read(
+                &driver.request(),
+                attr.ino,
+                &handle,
+                offset,
+                100,
+                libc::O_RDONLY,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(range, data[offset as usize..][..100]);
+    }
+}
+
+#[tokio::test]
+async fn exports_once_per_table_version() {
+    let ctx = create_context();
+    let numbers = Arc::new(Numbers::new());
+    ctx.register_table("numbers", numbers.clone()).unwrap();
+    let driver = driver_with(&ctx);
+    let path = "/datafusion/public/numbers/data.parquet";
+
+    // Concurrent misses wait for the same export.
+    let attr = driver.assert_exists(path).await;
+    let (a, b, c) = tokio::join!(
+        driver.getattr(attr.ino),
+        driver.read(attr.ino),
+        driver.read_path(path),
+    );
+    assert_eq!(a.unwrap().size, attr.size);
+    assert_eq!(b.unwrap(), c.unwrap());
+    assert_eq!(numbers.scans.load(Ordering::Relaxed), 1);
+
+    // Registering the table again exports it again.
+    let again = Arc::new(Numbers::new());
+    ctx.deregister_table("numbers").unwrap();
+    ctx.register_table("numbers", again.clone()).unwrap();
+
+    driver.assert_exists(path).await;
+    assert_eq!(again.scans.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,135 @@
+//! Run with `cargo test -p fuser-datafusion --features large-binary`.
+#![cfg(feature = "large-binary")]
+
+mod common;
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, UInt64Array},
+        record_batch::RecordBatch,
+    },
+    datasource::MemTable,
+};
+use fuser_async::{async_filesystem::AsyncFilesystem, testing::TestDriver};
+use fuser_datafusion::{
+    BinArray, DatafusionFs, DatafusionFsOptions, CHUNKS_SCHEMA, CHUNKS_TABLE, CONTENT_SCHEMA,
+    CONTENT_TABLE,
+};
+
+const CHUNK_SIZE: u64 = 64 << 20;
+const CHUNKS: u64 = 80;
+const LAST_CHUNK_SIZE: u64 = 1000;
+/// Past `u32::MAX`, so that offsets and sizes overflow if truncated.
+const FILE_SIZE: u64 = (CHUNKS - 1) * CHUNK_SIZE + LAST_CHUNK_SIZE;
+
+/// Byte `offset` of the large file.
+fn byte(offset: u64) -> u8 {
+    (offset % CHUNK_SIZE % 251) as u8
+}
+
+fn chunk(size: u64) -> ArrayRef {
+    let data: Vec<u8> = (0..size).map(byte).collect();
+    Arc::new(BinArray::from(vec![data.as_slice()]))
+}
+
+/// The example dataset, with `hello.txt` replaced by a file of `FILE_SIZE` bytes.
+///
+/// Every full chunk shares the same buffer, so the file only takes one chunk of
+/// memory.
+async fn driver() -> TestDriver<DatafusionFs> {
+    let ctx = common::load_csv().await.unwrap();
+
+    let full = chunk(CHUNK_SIZE);
+    let last = chunk(LAST_CHUNK_SIZE);
+    let batches = (0..CHUNKS)
+        .map(|i| {
+            let data = if i + 1 < CHUNKS { &full } else { &last };
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(UInt64Array::from(vec![2])),
+                Arc::new(UInt64Array::from(vec![i])),
+                data.clone(),
+            ];
+            RecordBatch::try_new(CHUNKS_SCHEMA.clone(), columns).unwrap()
+        })
+        .collect();
+    let chunks = MemTable::try_new(CHUNKS_SCHEMA.clone(), vec![batches]).unwrap();
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(vec![2])),
+        Arc::new(UInt64Array::from(vec![FILE_SIZE])),
+        Arc::new(BinArray::from(vec![None::<&[u8]>])),
+    ];
+    let content = RecordBatch::try_new(CONTENT_SCHEMA.clone(), columns).unwrap();
+    let content = MemTable::try_new(CONTENT_SCHEMA.clone(), vec![vec![content]]).unwrap();
+
+    ctx.deregister_table(CONTENT_TABLE).unwrap();
+    ctx.register_table(CONTENT_TABLE, Arc::new(content))
+        .unwrap();
+    ctx.register_table(CHUNKS_TABLE, Arc::new(chunks)).unwrap();
+
+    let fs = DatafusionFs::try_new(ctx)
+        .await
+        .unwrap()
+        .with_options(DatafusionFsOptions {
+            chunk_size: CHUNK_SIZE,
+            ..Default::default()
+        });
+
+    TestDriver::new(fs)
+}
+
+async fn read_at(driver: &TestDriver<DatafusionFs>, offset: u64, size: u32) -> Vec<u8> {
+    let fs = driver.fs();
+    let (handle, _) = fs.open(&driver.request(), 2, libc::O_RDONLY).await.unwrap();
+    let data = fs
+        .read(
+            &driver.request(),
+            2,
+            &handle,
+            offset as i64,
+            size,
+            libc::O_RDONLY,
+            None,
+        )
+        .await
+        .unwrap();
+    fs.release(&driver.request(), 2, &handle, libc::O_RDONLY, None, false)
+        .await
+        .unwrap();
+
+    data
+}
+
+fn expected(offset: u64, size: u64) -> Vec<u8> {
+    (offset..(offset + size).min(FILE_SIZE)).map(byte).collect()
+}
+
+#[tokio::test]
+async fn reads_a_file_larger_than_4_gib() {
+    let driver = driver().await;
+
+    let attr = driver.assert_exists("/hello.txt").await;
+    assert_eq!(attr.size, FILE_SIZE);
+
+    for offset in [
+        0,
+        CHUNK_SIZE - 10,
+        u32::MAX as u64 - 10,
+        (1 << 32) + 7,
+        70 * CHUNK_SIZE - 4096,
+        FILE_SIZE - 100,
+    ] {
+        assert_eq!(
+            read_at(&driver, offset, 1 << 16).await,
+            expected(offset, 1 << 16),
+            "read at {offset}"
+        );
+    }
+
+    assert!(read_at(&driver, FILE_SIZE, 10).await.is_empty());
+    assert!(read_at(&driver, FILE_SIZE + CHUNK_SIZE, 10)
+        .await
+        .is_empty());
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::util::pretty::pretty_format_batches, datasource::MemTable, prelude::SessionContext,
+};
+use fuser_async::{
+    async_filesystem::AsyncFilesystem,
+    testing::{TestDriver, ROOT_INO},
+};
+use fuser_datafusion::{DatafusionFs, METADATA_TABLE};
+
+async fn query(ctx: &SessionContext, sql: &str) -> String {
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn resolves_paths() {
+    let ctx = common::load_csv().await.unwrap();
+
+    assert_eq!(
+        query(&ctx, "SELECT ino, path FROM paths ORDER BY path").await,
+        "\
++-----+------------+
+| ino | path       |
++-----+------------+
+| 1   | /          |
+| 3   | /hello.lnk |
+| 2   | /hello.txt |
++-----+------------+"
+    );
+
+    // Equality filters start from the inode or path they name.
+    assert_eq!(
+        query(
+            &ctx,
+            "SELECT ino, path FROM paths WHERE ino = 3
+            UNION ALL SELECT ino, path FROM paths WHERE path = '/hello.txt'
+            UNION ALL SELECT ino, path FROM paths WHERE path IN ('/hello.txt/', '/missing')
+            ORDER BY ino",
+        )
+        .await,
+        "\
++-----+------------+
+| ino | path       |
++-----+------------+
+| 2   | /hello.txt |
+| 3   | /hello.lnk |
++-----+------------+"
+    );
+}
+
+#[tokio::test]
+async fn lists_subtrees() {
+    let ctx = common::load_csv().await.unwrap();
+
+    assert_eq!(
+        query(
+            &ctx,
+            "SELECT root, root_path, ino, path FROM subtree
+            WHERE root_path = '/' OR root = 2 ORDER BY root, path",
+        )
+        .await,
+        "\
++------+------------+-----+------------+
+| root | root_path  | ino | path       |
++------+------------+-----+------------+
+| 1    | /          | 1   | /          |
+| 1    | /          | 3   | /hello.lnk |
+| 1    | /          | 2   | /hello.txt |
+| 2    | /hello.txt | 2   | /hello.txt |
++------+------------+-----+------------+"
+    );
+    assert_eq!(
+        query(
+            &ctx,
+            "SELECT ino FROM subtree WHERE root_path = '/hello.txt'
+            UNION ALL SELECT ino FROM subtree WHERE root = 3",
+        )
+        .await,
+        "\
++-----+
+| ino |
++-----+
+| 2   |
+| 3   |
++-----+"
+    );
+}
+
+#[tokio::test]
+async fn follows_the_metadata_table() {
+    let ctx = common::load_csv().await.unwrap();
+    let sql = "SELECT ino, path FROM paths ORDER BY path";
+
+    let df = ctx
+        .sql("SELECT * FROM metadata WHERE name <> 'hello.txt'")
+        .await
+        .unwrap();
+    let schema = Arc::new(df.schema().into());
+    let batches = df.collect().await.unwrap();
+    ctx.deregister_table(METADATA_TABLE).unwrap();
+    ctx.register_table(
+        METADATA_TABLE,
+        Arc::new(MemTable::try_new(schema, vec![batches]).unwrap()),
+    )
+    .unwrap();
+
+    assert_eq!(
+        query(&ctx, sql).await,
+        "\
++-----+------------+
+| ino | path       |
++-----+------------+
+| 1   | /          |
+| 3   | /hello.lnk |
++-----+------------+"
+    );
+}
+
+#[tokio::test]
+async fn follows_changes_of_writable_filesystems() {
+    let ctx = common::load_csv().await.unwrap();
+    let fs = DatafusionFs::try_new_writable(ctx)
+        .await
+        .unwrap()
+        .with_query_dir(
+            "under_d",
+            "SELECT m.ino, m.name, m.type FROM metadata m JOIN subtree s ON m.ino = s.ino
+            WHERE s.root_path = '/d' AND m.name NOT IN ('.', '..')
+            ORDER BY m.name",
+        );
+    let driver = TestDriver::new(fs);
+    let fs = driver.fs();
+
+    driver.assert_dir_entries::<&str>("/under_d", &[]).await;
+
+    let (_, d, _) = fs
+        .mkdir(&driver.request(), ROOT_INO, "dir", 0o755, 0)
+        .await
+        .unwrap();
+    fs.mkdir(&driver.request(), d.ino, "sub", 0o755, 0)
+        .await
+        .unwrap();
+    fs.rename(&driver.request(), ROOT_INO, "dir", ROOT_INO, "d", 0)
+        .await
+        .unwrap();
+
+    driver.assert_dir_entries("/under_d", &["d", "sub"]).await;
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use std::sync::Arc;
+
+use datafusion::{arrow::datatypes::DataType, datasource::MemTable, prelude::SessionContext};
+use fuser_async::testing::TestDriver;
+use fuser_datafusion::{errors::DatafusionFsError, DatafusionFs, METADATA_TABLE};
+
+/// Replace the metadata table of `ctx` with the rows of `sql`.
+async fn replace_metadata(ctx: &SessionContext, sql: &str) {
+    let df = ctx.sql(sql).await.unwrap();
+    let schema = Arc::new(df.schema().into());
+    let batches = df.collect().await.unwrap();
+
+    ctx.deregister_table(METADATA_TABLE).unwrap();
+    ctx.register_table(
+        METADATA_TABLE,
+        Arc::new(MemTable::try_new(schema, vec![batches]).unwrap()),
+    )
+    .unwrap();
+}
+
+async fn mismatch(sql: &str) -> (String, String, DataType) {
+    let ctx = common::load_csv().await.unwrap();
+    replace_metadata(&ctx, sql).await;
+
+    match DatafusionFs::try_new(ctx).await.map(drop) {
+        Err(DatafusionFsError::SchemaMismatch(table, column, data_type)) => {
+            (table, column, data_type)
+        }
+        r => panic!("expected a schema mismatch, got {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn casts_widening_columns() {
+    let ctx = common::load_csv().await.unwrap();
+    replace_metadata(
+        &ctx,
+        "SELECT CAST(ino AS INT UNSIGNED) AS ino, id, type, arrow_cast(name, 'LargeUtf8')
+            AS name, parent_ino, atime, mtime, ctime, target,
+            CAST(420 AS SMALLINT UNSIGNED) AS mode
+        FROM metadata",
+    )
+    .await;
+
+    let driver = TestDriver::new(DatafusionFs::try_new(ctx).await.unwrap());
+    let hello = driver.assert_exists("/hello.txt").await;
+    assert_eq!((hello.ino, hello.perm), (2, 0o644));
+    driver.assert_content("/hello.txt", "Hello world!").await;
+}
+
+#[tokio::test]
+async fn rejects_signed_columns() {
+    assert_eq!(
+        mismatch(
+            "SELECT CAST(ino AS BIGINT) AS ino, id, type, name, parent_ino, atime, mtime,
+                ctime, target
+            FROM metadata",
+        )
+        .await,
+        (
+            METADATA_TABLE.to_owned(),
+            "ino".to_owned(),
+            DataType::UInt64
+        )
+    );
+}
+
+#[tokio::test]
+async fn rejects_narrowing_columns() {
+    assert_eq!(
+        mismatch(
+            "SELECT ino, id, type, name, parent_ino, atime, mtime, ctime, target,
+                CAST(1000 AS BIGINT UNSIGNED) AS uid
+            FROM metadata",
+        )
+        .await,
+        (
+            METADATA_TABLE.to_owned(),
+            "uid".to_owned(),
+            DataType::UInt32
+        )
+    );
+}
+
+#[tokio::test]
+async fn rejects_missing_columns() {
+    assert_eq!(
+        mismatch("SELECT ino, id, type, parent_ino, atime, mtime, ctime FROM metadata").await,
+        (METADATA_TABLE.to_owned(), "name".to_owned(), DataType::Utf8)
+    );
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+use fuser_async::{
+    async_filesystem::AsyncFilesystem,
+    fuser::{FileAttr, FileType},
+    testing::TestDriver,
+};
+use fuser_datafusion::{
+    parquet::{snapshot, ParquetLoader, SnapshotOptions},
+    DatafusionFs, DatafusionFsOptions, CHUNKS_TABLE, CONTENT_TABLE, METADATA_TABLE,
+};
+
+/// A tree with nested directories, a symlink, a hard link, an empty file and a
+/// file spanning many chunks.
+fn source_tree(root: &Path) {
+    fs::create_dir_all(root.join("dir/nested")).unwrap();
+    fs::create_dir(root.join("empty_dir")).unwrap();
+
+    fs::write(root.join("a.txt"), "hello").unwrap();
+    fs::write(root.join("empty"), "").unwrap();
+    fs::write(root.join("dir/b.txt"), "in a directory").unwrap();
+    fs::write(root.join("dir/nested/c.txt"), "deeper").unwrap();
+
+    let big: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(root.join("dir/big.bin"), big).unwrap();
+
+    fs::hard_link(root.join("a.txt"), root.join("dir/a_link.txt")).unwrap();
+    std::os::unix::fs::symlink("dir/b.txt", root.join("b.lnk")).unwrap();
+
+    fs::set_permissions(root.join("dir/b.txt"), fs::Permissions::from_mode(0o600)).unwrap();
+}
+
+fn micros(secs: i64, nsecs: i64) -> i64 {
+    secs * 1_000_000 + nsecs / 1_000
+}
+
+fn attr_micros(time: std::time::SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).unwrap().as_micros() as i64
+}
+
+fn assert_attr(path: &str, attr: &FileAttr, meta: &fs::Metadata) {
+    let kind = match meta.file_type() {
+        t if t.is_dir() => FileType::Directory,
+        t if t.is_symlink() => FileType::Symlink,
+        _ => FileType::RegularFile,
+    };
+
+    assert_eq!(attr.kind, kind, "kind of {path}");
+    assert_eq!(attr.perm as u32, meta.mode() & 0o7777, "mode of {path}");
+    assert_eq!(attr.uid, meta.uid(), "uid of {path}");
+    assert_eq!(attr.gid, meta.gid(), "gid of {path}");
+    assert_eq!(attr.nlink as u64, meta.nlink(), "nlink of {path}");
+    assert_eq!(
+        attr_micros(attr.mtime),
+        micros(meta.mtime(), meta.mtime_nsec()),
+        "mtime of {path}"
+    );
+
+    if kind != FileType::Directory {
+        assert_eq!(attr.size, meta.len(), "size of {path}");
+    }
+}
+
+/// Compare every entry of the tree at `source` with its counterpart in the
+/// filesystem.
+async fn assert_tree(driver: &TestDriver<DatafusionFs>, source: &Path) {
+    let mut dirs = vec!["/".to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut names = vec![];
+
+        for entry in fs::read_dir(source.join(dir.trim_start_matches('/'))).unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.file_name().into_string().unwrap();
+            let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+            let meta = fs::symlink_metadata(entry.path()).unwrap();
+
+            let attr = driver.assert_exists(&path).await;
+            assert_attr(&path, &attr, &meta);
+
+            if meta.is_file() {
+                driver
+                    .assert_content(&path, fs::read(entry.path()).unwrap())
+                    .await;
+            } else if meta.is_symlink() {
+                let target = driver
+                    .fs()
+                    .readlink(&driver.request(), attr.ino)
+                    .await
+                    .unwrap();
+                let expected = fs::read_link(entry.path()).unwrap();
+                assert_eq!(target, expected.to_str().unwrap().as_bytes(), "{path}");
+            } else {
+                dirs.push(path);
+            }
+
+            names.push(name);
+        }
+
+        driver.assert_dir_entries(&dir, &names).await;
+    }
+}
+
+async fn round_trip(options: SnapshotOptions) {
+    let source = tempfile::tempdir().unwrap();
+    let dest = tempfile::tempdir().unwrap();
+    source_tree(source.path());
+
+    let stats = snapshot(source.path(), dest.path(), &options).unwrap();
+    assert_eq!(stats.files, 5);
+    assert_eq!(stats.bytes, 300_000 + 5 + 14 + 6);
+
+    let path = |table: &str| dest.path().join(table).to_str().unwrap().to_owned();
+    let mut loader = ParquetLoader::new(path(METADATA_TABLE), path(CONTENT_TABLE));
+    let mut fs_options = DatafusionFsOptions::default();
+    if let Some(chunk_size) = options.chunk_size {
+        loader = loader.with_chunks(path(CHUNKS_TABLE));
+        fs_options.chunk_size = chunk_size;
+    }
+
+    let fs = DatafusionFs::try_new(loader.load().await.unwrap())
+        .await
+        .unwrap()
+        .with_options(fs_options);
+    let driver = TestDriver::new(fs).with_read_chunk(64 * 1024);
+
+    let root = driver.assert_exists("/").await;
+    assert_attr("/", &root, &fs::metadata(source.path()).unwrap());
+    assert_tree(&driver, source.path()).await;
+
+    let a = driver.assert_exists("/a.txt").await;
+    let link = driver.assert_exists("/dir/a_link.txt").await;
+    assert_eq!(a.ino, link.ino);
+}
+
+#[tokio::test]
+async fn round_trip_inline() {
+    round_trip(SnapshotOptions::default()).await;
+}
+
+#[tokio::test]
+async fn round_trip_chunked() {
+    round_trip(SnapshotOptions {
+        chunk_size: Some(1000),
+        ..Default::default()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn round_trip_split_files() {
+    round_trip(SnapshotOptions {
+        max_file_size: Some(64 * 1024),
+        chunk_size: Some(1000),
+    })
+    .await;
+}
@@ -0,0 +1,329 @@
+mod common;
+
+use fuser_async::{
+    async_filesystem::{AsyncFilesystem, SetAttr},
+    errors::ToErrno,
+    fuser::FileAttr,
+    testing::{TestDriver, ROOT_INO},
+};
+use fuser_datafusion::{DatafusionFs, DatafusionFsOptions};
+
+const OWNER: (u32, u32) = (501, 20);
+const OTHER: (u32, u32) = (1000, 1000);
+
+async fn driver() -> TestDriver<DatafusionFs> {
+    let ctx = common::load_csv().await.unwrap();
+    TestDriver::new(DatafusionFs::try_new_writable(ctx).await.unwrap())
+}
+
+/// A driver on the filesystem of `driver`, issuing requests as `(uid, gid)`.
+fn as_user(driver: &TestDriver<DatafusionFs>, (uid, gid): (u32, u32)) -> TestDriver<DatafusionFs> {
+    TestDriver::from_arc(driver.fs().clone()).with_user(uid, gid)
+}
+
+/// Create `name` in `parent` with `content`.
+async fn create(
+    driver: &TestDriver<DatafusionFs>,
+    parent: u64,
+    name: &str,
+    content: &[u8],
+) -> Result<FileAttr, i32> {
+    let fs = driver.fs();
+    let (_, attr, _, handle, _) = fs
+        .create(&driver.request(), parent, name, 0o644, 0o022, 0)
+        .await
+        .map_err(|e| e.errno())?;
+
+    fs.write(&driver.request(), attr.ino, &handle, 0, content, 0, 0, None)
+        .await
+        .unwrap();
+    fs.release(&driver.request(), attr.ino, &handle, 0, None, true)
+        .await
+        .unwrap();
+
+    Ok(attr)
+}
+
+async fn setattr(
+    driver: &TestDriver<DatafusionFs>,
+    ino: u64,
+    attr: SetAttr,
+) -> Result<FileAttr, i32> {
+    match driver
+        .fs()
+        .setattr(&driver.request(), ino, None, attr)
+        .await
+    {
+        Ok((_, attr)) => Ok(attr),
+        Err(e) => Err(e.errno()),
+    }
+}
+
+#[tokio::test]
+async fn rejects_files_over_the_maximum_size() {
+    let ctx = common::load_csv().await.unwrap();
+    let fs = DatafusionFs::try_new_writable(ctx)
+        .await
+        .unwrap()
+        .with_options(DatafusionFsOptions {
+            max_file_size: 1024,
+            ..Default::default()
+        });
+    let driver = TestDriver::new(fs);
+    let fs = driver.fs();
+
+    let (_, attr, _, handle, _) = fs
+        .create(&driver.request(), ROOT_INO, "big.txt", 0o644, 0, 0)
+        .await
+        .unwrap();
+
+    for offset in [1 << 40, 1020, i64::MAX] {
+        let e = fs
+            .write(
+                &driver.request(),
+                attr.ino,
+                &handle,
+                offset,
+                b"hello",
+                0,
+                0,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(e.errno(), libc::EFBIG);
+    }
+    fs.write(
+        &driver.request(),
+        attr.ino,
+        &handle,
+        1019,
+        b"hello",
+        0,
+        0,
+        None,
+    )
+    .await
+    .unwrap();
+    fs.release(&driver.request(), attr.ino, &handle, 0, None, true)
+        .await
+        .unwrap();
+
+    let truncate = |size| SetAttr {
+        size: Some(size),
+        ..Default::default()
+    };
+    assert_eq!(
+        setattr(&driver, attr.ino, truncate(1 << 40)).await,
+        Err(libc::EFBIG)
+    );
+    assert_eq!(
+        setattr(&driver, attr.ino, truncate(1024))
+            .await
+            .unwrap()
+            .size,
+        1024
+    );
+}
+
+#[tokio::test]
+async fn checks_permissions_of_other_users() {
+    let driver = driver().await;
+    let other = as_user(&driver, OTHER);
+    let fs = other.fs();
+    let hello = other.assert_exists("/hello.txt").await;
+
+    // Readable by everyone, writable by its owner.
+    assert_eq!(
+        other.read_path("/hello.txt").await.unwrap(),
+        b"Hello world!"
+    );
+    let e = fs
+        .open(&other.request(), hello.ino, libc::O_WRONLY)
+        .await
+        .map(drop)
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EACCES);
+
+    assert_eq!(
+        create(&other, ROOT_INO, "new.txt", b"").await,
+        Err(libc::EACCES)
+    );
+    let e = fs
+        .unlink(&other.request(), ROOT_INO, "hello.txt")
+        .await
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EACCES);
+    let e = fs
+        .rename(
+            &other.request(),
+            ROOT_INO,
+            "hello.txt",
+            ROOT_INO,
+            "moved.txt",
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EACCES);
+
+    for attr in [
+        SetAttr {
+            mode: Some(0o777),
+            ..Default::default()
+        },
+        SetAttr {
+            uid: Some(OTHER.0),
+            ..Default::default()
+        },
+        SetAttr {
+            gid: Some(OTHER.1),
+            ..Default::default()
+        },
+    ] {
+        assert_eq!(setattr(&other, hello.ino, attr).await, Err(libc::EPERM));
+    }
+    // Setting the times or the size needs write access.
+    for attr in [
+        SetAttr {
+            mtime: Some(std::time::UNIX_EPOCH),
+            ..Default::default()
+        },
+        SetAttr {
+            size: Some(0),
+            ..Default::default()
+        },
+    ] {
+        assert_eq!(setattr(&other, hello.ino, attr).await, Err(libc::EACCES));
+    }
+
+    other.assert_content("/hello.txt", "Hello world!").await;
+}
+
+#[tokio::test]
+async fn lets_owners_change_their_files() {
+    let driver = driver().await;
+    let owner = as_user(&driver, OWNER);
+    let hello = owner.assert_exists("/hello.txt").await;
+
+    let attr = setattr(
+        &owner,
+        hello.ino,
+        SetAttr {
+            mode: Some(0o600),
+            gid: Some(OWNER.1),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(attr.perm, 0o600);
+
+    // Only root gives files away.
+    let chown = SetAttr {
+        uid: Some(OTHER.0),
+        ..Default::default()
+    };
+    assert_eq!(setattr(&owner, hello.ino, chown).await, Err(libc::EPERM));
+    assert_eq!(
+        setattr(&driver, hello.ino, chown).await.unwrap().uid,
+        OTHER.0
+    );
+
+    let attr = create(&owner, ROOT_INO, "new.txt", b"new\n").await.unwrap();
+    assert_eq!((attr.uid, attr.gid), OWNER);
+    owner.assert_content("/new.txt", "new\n").await;
+}
+
+#[tokio::test]
+async fn sticky_directories_keep_entries_of_others() {
+    let driver = driver().await;
+    let (_, tmp, _) = driver
+        .fs()
+        .mkdir(&driver.request(), ROOT_INO, "tmp", 0o1777, 0)
+        .await
+        .unwrap();
+
+    let owner = as_user(&driver, OWNER);
+    let other = as_user(&driver, OTHER);
+    create(&other, tmp.ino, "mine.txt", b"").await.unwrap();
+
+    let e = owner
+        .fs()
+        .unlink(&owner.request(), tmp.ino, "mine.txt")
+        .await
+        .unwrap_err();
+    assert_eq!(e.errno(), libc::EPERM);
+
+    other
+        .fs()
+        .unlink(&other.request(), tmp.ino, "mine.txt")
+        .await
+        .unwrap();
+    driver.assert_dir_entries::<&str>("/tmp", &[]).await;
+}
+
+#[tokio::test]
+async fn keeps_many_changes() {
+    let driver = driver().await;
+    let fs = driver.fs();
+
+    // Each change adds batches to the tables, enough for a few compactions.
+    for i in 0..40 {
+        create(
+            &driver,
+            ROOT_INO,
+            &format!("{i}.txt"),
+            i.to_string().as_bytes(),
+        )
+        .await
+        .unwrap();
+    }
+    for i in (0..40).step_by(2) {
+        fs.rename(
+            &driver.request(),
+            ROOT_INO,
+            &format!("{i}.txt"),
+            ROOT_INO,
+            &format!("{i}.md"),
+            0,
+        )
+        .await
+        .unwrap();
+    }
+    for i in (0..40).step_by(4) {
+        fs.unlink(&driver.request(), ROOT_INO, &format!("{i}.md"))
+            .await
+            .unwrap();
+    }
+
+    let entries = driver.readdir(ROOT_INO).await.unwrap();
+    assert_eq!(
+        entries.iter().filter(|e| e.name.ends_with(".txt")).count(),
+        21
+    );
+    assert_eq!(
+        entries.iter().filter(|e| e.name.ends_with(".md")).count(),
+        10
+    );
+
+    driver.assert_content("/2.md", "2").await;
+    driver.assert_content("/39.txt", "39").await;
+    driver.assert_not_found("/4.md").await;
+}
+
+#[tokio::test]
+async fn reports_free_space() {
+    let driver = driver().await;
+    let fs = driver.fs();
+
+    let before = fs.statfs(&driver.request(), ROOT_INO).await.unwrap();
+    assert!(before.bfree > 0 && before.bavail == before.bfree);
+
+    create(&driver, ROOT_INO, "new.txt", &[0; 4096])
+        .await
+        .unwrap();
+    let after = fs.statfs(&driver.request(), ROOT_INO).await.unwrap();
+    assert_eq!(after.blocks - after.bfree, before.blocks - before.bfree + 8);
+    assert_eq!(after.files, before.files + 1);
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use std::sync::Arc;
+
+use datafusion::{
+    arrow::{
+        array::{ArrayRef, StringArray, UInt64Array},
+        record_batch::RecordBatch,
+    },
+    datasource::MemTable,
+};
+use fuser_async::{async_filesystem::XattrReply, testing::TestDriver};
+use fuser_datafusion::{BinArray, DatafusionFs, XATTRS_SCHEMA, XATTRS_TABLE};
+
+const MIME_TYPE: &[u8] = b"text/plain";
+
+/// The example dataset, with a `user.mime_type` attribute on `hello.txt`.
+async fn driver() -> TestDriver<DatafusionFs> {
+    let ctx = common::load_csv().await.unwrap();
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(vec![2])),
+        Arc::new(StringArray::from(vec!["user.mime_type"])),
+        Arc::new(BinArray::from(vec![MIME_TYPE])),
+    ];
+    let batch = RecordBatch::try_new(XATTRS_SCHEMA.clone(), columns).unwrap();
+    let table = MemTable::try_new(XATTRS_SCHEMA.clone(), vec![vec![batch]]).unwrap();
+    ctx.register_table(XATTRS_TABLE, Arc::new(table)).unwrap();
+
+    TestDriver::new(DatafusionFs::try_new(ctx).await.unwrap())
+}
+
+#[tokio::test]
+async fn getxattr_follows_the_buffer_size() {
+    let driver = driver().await;
+    let len = MIME_TYPE.len() as u32;
+
+    assert_eq!(
+        driver.getxattr(2, "user.mime_type", 0).await,
+        Ok(XattrReply::Size(len))
+    );
+    assert_eq!(
+        driver.getxattr(2, "user.mime_type", len - 1).await,
+        Err(libc::ERANGE)
+    );
+    assert_eq!(
+        driver.getxattr(2, "user.mime_type", len).await,
+        Ok(XattrReply::Data(MIME_TYPE.to_vec()))
+    );
+    assert_eq!(
+        driver.getxattr(2, "user.mime_type", 4096).await,
+        Ok(XattrReply::Data(MIME_TYPE.to_vec()))
+    );
+
+    assert_eq!(
+        driver.getxattr(2, "user.missing", 4096).await,
+        Err(libc::ENODATA)
+    );
+    assert_eq!(
+        driver.getxattr(3, "user.mime_type", 4096).await,
+        Err(libc::ENODATA)
+    );
+}
+
+#[tokio::test]
+async fn exposes_the_id_column() {
+    let driver = driver().await;
+
+    let Ok(XattrReply::Size(len)) = driver.getxattr(3, "user.id", 0).await else {
+        panic!("no user.id on hello.lnk");
+    };
+    // As written in the example CSV.
+    assert_eq!(
+        driver.getxattr(3, "user.id", len).await,
+        Ok(XattrReply::Data(b" 3".to_vec()))
+    );
+    assert_eq!(
+        driver.getxattr(3, "user.id", len - 1).await,
+        Err(libc::ERANGE)
+    );
+    assert_eq!(
+        driver.getxattr(99, "user.id", 4096).await,
+        Err(libc::ENOENT)
+    );
+}
+
+#[tokio::test]
+async fn listxattr_follows_the_buffer_size() {
+    let driver = driver().await;
+    let list = b"user.id\0user.mime_type\0".to_vec();
+    let len = list.len() as u32;
+
+    assert_eq!(driver.listxattr(2, 0).await, Ok(XattrReply::Size(len)));
+    assert_eq!(driver.listxattr(2, len - 1).await, Err(libc::ERANGE));
+    assert_eq!(driver.listxattr(2, len).await, Ok(XattrReply::Data(list)));
+
+    assert_eq!(
+        driver.listxattr(3, 4096).await,
+        Ok(XattrReply::Data(b"user.id\0".to_vec()))
+    );
+    assert_eq!(driver.listxattr(99, 4096).await, Err(libc::ENOENT));
+}
@@ -40,7 +40,7 @@ async fn main() -> anyhow::Result<()> {
                 match e {
                     Ok(DeviceEvent::Connection(b)) => {
                         info!("Connected to device: {:?}", b);
-                        let client = connect(
+                        connect(
                             &SETTINGS.config().device.ip,
                             SETTINGS.config().device.port,
                             &SETTINGS.config().device.login,
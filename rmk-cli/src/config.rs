@@ -33,6 +33,8 @@ pub struct Settings {
 }
 
 impl Settings {
+    // Not `Default`: loading creates the config directory and file.
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let config_path = DIRS.config_dir().join("config.toml");
 
@@ -80,11 +82,8 @@ impl Settings {
 
         fs::create_dir_all(DIRS.config_dir()).unwrap();
 
-        fs::write(
-            &config_path.clone(),
-            toml::to_string_pretty(&config).unwrap(),
-        )
-        .expect("Failed to write config");
+        fs::write(&config_path, toml::to_string_pretty(&config).unwrap())
+            .expect("Failed to write config");
 
         let settings = Settings {
             config,
@@ -98,7 +97,7 @@ impl Settings {
 
     pub fn save(&self) {
         fs::write(
-            &self.config_path.clone(),
+            &self.config_path,
             toml::to_string_pretty(&self.config).unwrap(),
         )
         .expect("Failed to write config");